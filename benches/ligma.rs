@@ -0,0 +1,168 @@
+// The numbers to watch when touching the lexer or either engine. Every
+// workload is a .ligma file in benches/workloads, so any of them can be run
+// and poked at on its own too. Timings are the best of RUNS, and anything
+// after the -- only runs the benchmarks with that in their name:
+//
+//     cargo bench --bench ligma
+//     cargo bench --bench ligma -- strings
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use wasm_ligma_interpreter::{parse_source, run_source, Engine, RunOptions};
+use wasm_ligma_interpreter::ligma::include::IncludeOptions;
+use wasm_ligma_interpreter::ligma::lexer::{Lexer, Token};
+
+const RUNS: usize = 7;
+// the lexer gets these glued together until there's at least this much
+const LEX_FILES: [&str; 4] = ["lexer", "loops", "recursion", "strings"];
+const LEX_BYTES: usize = 2 << 20;
+const PROGRAMS: [&str; 3] = ["loops", "recursion", "strings"];
+// one parse of the include workload is over too quick to time on its own
+const INCLUDE_PARSES: usize = 100;
+
+fn workload(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("benches");
+    path.push("workloads");
+    path.push(name);
+    return path;
+}
+
+fn read(name: &str) -> String {
+    let path = workload(&format!("{}.ligma", name));
+    match fs::read_to_string(&path) {
+        Ok(source) => {
+            return source;
+        }
+        Err(e) => {
+            panic!("can't read {}: {}", path.display(), e);
+        }
+    }
+}
+
+// best of RUNS, the rest is noise from whatever else the machine was doing
+fn best<F: FnMut()>(mut f: F) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    return best;
+}
+
+fn report(name: &str, took: Duration, note: String) {
+    let line = format!("{:<24} {:>10.2}ms  {}", name, took.as_secs_f64() * 1000.0, note);
+    println!("{}", line.trim_end());
+}
+
+// one lex_next_token at a time like lex_all does, minus keeping the tokens
+fn lexer() {
+    let mut piece = String::new();
+    for name in LEX_FILES {
+        piece.push_str(&read(name));
+        piece.push('\n');
+    }
+    let mut source = String::new();
+    while source.len() < LEX_BYTES {
+        source.push_str(&piece);
+    }
+    let mut tokens = 0;
+    let took = best(|| {
+        let mut lexer = Lexer::new(&source);
+        tokens = 0;
+        loop {
+            let spanned = lexer.lex_next_token();
+            if let Token::EOF = spanned.token {
+                break;
+            }
+            tokens += 1;
+        }
+        if lexer.diagnostics().len() > 0 {
+            panic!("the lexer workload has bad tokens: {:?}", lexer.diagnostics()[0]);
+        }
+    });
+    let secs = took.as_secs_f64();
+    let note = format!("{:.1} MB/s, {:.2}M tokens/s", source.len() as f64 / secs / 1e6, tokens as f64 / secs / 1e6);
+    report("lex", took, note);
+}
+
+fn run(source: &str, engine: Engine) -> String {
+    let options = RunOptions {
+        engine,
+        ..RunOptions::default()
+    };
+    let result = run_source(source, options);
+    if !result.ok() {
+        panic!("benchmark program failed: {:?}", result.errors);
+    }
+    return result.output;
+}
+
+// both engines on the same program, after checking they still agree on it
+fn program(name: &str) {
+    let source = read(name);
+    let walked = run(&source, Engine::Walk);
+    if walked != run(&source, Engine::Bytecode) {
+        panic!("the engines disagree about {}", name);
+    }
+    let walk = best(|| {
+        run(&source, Engine::Walk);
+    });
+    let bytecode = best(|| {
+        run(&source, Engine::Bytecode);
+    });
+    let note = format!("{:.1}x", walk.as_secs_f64() / bytecode.as_secs_f64());
+    report(&format!("{} walk", name), walk, String::new());
+    report(&format!("{} bytecode", name), bytecode, note);
+}
+
+// lexing and parsing with every #[path] pulled in off the disk, nothing runs
+fn include() {
+    let path = workload("include/main.ligma");
+    let source = read("include/main");
+    let includes = IncludeOptions {
+        path: Some(path),
+        ..IncludeOptions::default()
+    };
+    let mut stmts = 0;
+    let took = best(|| {
+        for _ in 0..INCLUDE_PARSES {
+            match parse_source(&source, &includes) {
+                Ok(program) => {
+                    stmts = program.len();
+                }
+                Err(errors) => {
+                    panic!("the include workload doesn't parse: {:?}", errors);
+                }
+            }
+        }
+    });
+    let each = took.as_secs_f64() / INCLUDE_PARSES as f64;
+    let note = format!("{} parses, {:.0}us each, {} statements", INCLUDE_PARSES, each * 1e6, stmts);
+    report("include", took, note);
+}
+
+fn main() {
+    // cargo bench hands over a --bench of its own
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let wanted = |name: &str| -> bool {
+        match &filter {
+            Some(filter) => name.contains(filter.as_str()),
+            None => true,
+        }
+    };
+    println!("best of {}", RUNS);
+    if wanted("lex") {
+        lexer();
+    }
+    for name in PROGRAMS {
+        if wanted(name) {
+            program(name);
+        }
+    }
+    if wanted("include") {
+        include();
+    }
+}
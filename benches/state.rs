@@ -0,0 +1,75 @@
+// How long the tree walker takes on programs that lean on what all its frames
+// share: calls, the argreg, fuel and output all go through it every step.
+// The programs do plenty besides, so it starts with what one trip to the
+// shared state costs on its own. Build it both ways to see what Rc<RefCell>
+// saves over Arc<Mutex>:
+//
+//     cargo bench --bench state
+//     cargo bench --bench state --features sync
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use wasm_ligma_interpreter::{run_source, Engine, RunOptions};
+use wasm_ligma_interpreter::ligma::shared::Shared;
+
+const RUNS: usize = 7;
+const TRIPS: u32 = 10_000_000;
+
+const PROGRAMS: [(&str, &str); 5] = [
+    ("loop", "i = 0; s = 0; $(i < 200000){ s = s + i; i = i + 1; }"),
+    ("calls", "f(a){ @@ a + 1; } i = 0; $(i < 50000){ i = f(i); }"),
+    ("fib", "fib(n){ ?(n < 2){ @@ n; } @@ fib(n - 1) + fib(n - 2); } r = fib(18);"),
+    ("argreg", "i = 0; $(i < 200000){ @ = i; j = @; i = i + 1; }"),
+    ("output", "i = 0; $(i < 100000){ !i; i = i + 1; }"),
+];
+
+// best of RUNS, the rest is noise from whatever else the machine was doing
+fn time(source: &str, fuel: Option<u64>) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let options = RunOptions {
+            engine: Engine::Walk,
+            fuel,
+            ..RunOptions::default()
+        };
+        let start = Instant::now();
+        let result = run_source(source, options);
+        let took = start.elapsed();
+        if !result.ok() {
+            panic!("benchmark program failed: {:?}", result.errors);
+        }
+        best = best.min(took);
+    }
+    return best;
+}
+
+// what Fun::burn does every statement, minus the fuel running out
+fn trips() -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let fuel = Shared::new(Some(u64::MAX));
+        let start = Instant::now();
+        for _ in 0..TRIPS {
+            let mut fuel = black_box(&fuel).get();
+            if let Some(steps) = *fuel {
+                *fuel = Some(steps - 1);
+            }
+        }
+        best = best.min(start.elapsed());
+    }
+    return best;
+}
+
+fn main() {
+    let state = match cfg!(feature = "sync") {
+        true => "Arc<Mutex>",
+        false => "Rc<RefCell>",
+    };
+    println!("walk engine, shared state in {}, best of {}", state, RUNS);
+    println!("one trip to the shared state {:>10.2}ns", trips().as_secs_f64() * 1e9 / TRIPS as f64);
+    println!("{:<8} {:>12} {:>12}", "", "no fuel", "fuel");
+    for (name, source) in PROGRAMS {
+        let free = time(source, None);
+        let metered = time(source, Some(u64::MAX));
+        println!("{:<8} {:>10.2}ms {:>10.2}ms", name, free.as_secs_f64() * 1000.0, metered.as_secs_f64() * 1000.0);
+    }
+}
@@ -2,49 +2,457 @@ mod utils;
 
 use wasm_bindgen::prelude::*;
 
-mod ligma;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub mod ligma;
 use ligma::{
     fun::Fun as Fun,
-    lexer::Lexer as Lexer,
+    config::InterpreterConfig,
     lexer::Token as Token,
+    include::IncludeOptions,
+    files::{FileProvider, MemoryFiles},
     expr::Expr as Expr,
+    input::{InputSource, QueueInput},
+    output::{self, CappedSink, OutputSink},
+    shared::Shared,
 };
+pub use ligma::run::{parse_source, parse_source_with, run_source, Engine, RunOptions, RunResult};
 
 #[wasm_bindgen]
 extern "C" {
     fn alert(s: &str);
 }
 
+// ??name; in the browser asks whatever function the page handed over.
+// Anything that isn't a string back (null from a cancelled prompt) is the end of input.
+struct JsInput {
+    callback: js_sys::Function,
+}
+
+impl InputSource for JsInput {
+    fn read_line(&mut self) -> Option<String> {
+        match self.callback.call0(&JsValue::NULL) {
+            Ok(line) => {
+                return line.as_string();
+            }
+            Err(_) => {
+                return None;
+            }
+        }
+    }
+}
+
+// !name; in the browser goes to whatever function the page handed over, one
+// chunk at a time. The whole run is one call the page waits on, so nothing
+// gets painted until it's over either way, this just means the chunks don't
+// pile up in here. Past OUTPUT_LIMIT bytes the rest is dropped like
+// CappedSink does, or ten million steps of !"x"; would all land on the page.
+struct JsOutput {
+    callback: js_sys::Function,
+    room: usize,
+    truncated: bool,
+}
+
+impl JsOutput {
+    fn new(callback: js_sys::Function) -> JsOutput {
+        JsOutput {
+            callback,
+            room: OUTPUT_LIMIT,
+            truncated: false,
+        }
+    }
+
+    fn send(&self, chunk: &str) {
+        let _ = self.callback.call1(&JsValue::NULL, &JsValue::from_str(chunk));
+    }
+}
+
+impl OutputSink for JsOutput {
+    fn write(&mut self, chunk: &str) {
+        if self.truncated {
+            return;
+        }
+        if chunk.len() <= self.room {
+            self.room -= chunk.len();
+            self.send(chunk);
+            return;
+        }
+        self.send(output::fit(chunk, self.room));
+        self.send(&output::cut_off(OUTPUT_LIMIT));
+        self.truncated = true;
+    }
+}
+
+// nobody is reading a megabyte of fizzbuzz out of a textarea
+const OUTPUT_LIMIT: usize = 1 << 20;
+
+// there is no stdin in a browser tab, so without a callback there's just nothing to read
 #[wasm_bindgen]
 pub fn ligma(input: String) -> String{
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut lexer = Lexer::new(&input);
-    loop {
-        let token = lexer.lex_next_token();
-        match token {
-            Token::EOF => {
-                break;
-            }
-            Token::Include(to_include) => {
-                for token_to_include in to_include {
-                    tokens.push(token_to_include);
+    return run(&input, Box::new(QueueInput::new(Vec::new())), Box::new(CappedSink::new(OUTPUT_LIMIT)), None, IncludeOptions::default());
+}
+
+#[wasm_bindgen]
+pub fn ligma_with_input(input: String, read_line: js_sys::Function) -> String{
+    return run(&input, Box::new(JsInput { callback: read_line }), Box::new(CappedSink::new(OUTPUT_LIMIT)), None, IncludeOptions::default());
+}
+
+// stops with an out of fuel error after that many steps instead of locking up the tab
+#[wasm_bindgen]
+pub fn ligma_with_fuel(input: String, fuel: f64) -> String{
+    return run(&input, Box::new(QueueInput::new(Vec::new())), Box::new(CappedSink::new(OUTPUT_LIMIT)), steps(Some(fuel)), IncludeOptions::default());
+}
+
+// everything, banner and errors included, goes to write as it's printed, up
+// to OUTPUT_LIMIT bytes, so what comes back is always empty. Leave fuel
+// undefined to run forever.
+#[wasm_bindgen]
+pub fn ligma_streaming(input: String, write: js_sys::Function, read_line: js_sys::Function, fuel: Option<f64>) -> String{
+    return run(&input, Box::new(JsInput { callback: read_line }), Box::new(JsOutput::new(write)), steps(fuel), IncludeOptions::default());
+}
+
+// A bunch of named sources for #[path] to pick from, since the page has no disk.
+// lib/x.ligma is just a name with a slash in it.
+#[wasm_bindgen]
+pub struct Files {
+    files: MemoryFiles,
+}
+
+#[wasm_bindgen]
+impl Files {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Files {
+        Files {
+            files: MemoryFiles::new(),
+        }
+    }
+
+    // replaces whatever was there under that name
+    pub fn add(&mut self, name: &str, source: &str) {
+        self.files.add(name, source);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        return self.files.remove(name);
+    }
+
+    pub fn names(&self) -> js_sys::Array {
+        return self.files.names().into_iter().map(|name| JsValue::from_str(&name)).collect();
+    }
+}
+
+impl Default for Files {
+    fn default() -> Files {
+        return Files::new();
+    }
+}
+
+impl Files {
+    fn includes(&self, entry: Option<&str>) -> IncludeOptions {
+        IncludeOptions {
+            path: entry.map(PathBuf::from),
+            search_paths: Vec::new(),
+            files: Arc::new(self.files.clone()),
+        }
+    }
+}
+
+// runs entry out of files, and its #[includes] come out of there too
+#[wasm_bindgen]
+pub fn ligma_with_files(files: &Files, entry: &str, fuel: Option<f64>) -> String{
+    let source = match files.files.read(entry.as_ref()) {
+        Ok(source) => source,
+        Err(_) => {
+            return format!("error: there's no file called `{}` to run\n", entry);
+        }
+    };
+    return run(&source, Box::new(QueueInput::new(Vec::new())), Box::new(CappedSink::new(OUTPUT_LIMIT)), steps(fuel), files.includes(Some(entry)));
+}
+
+// JS numbers are all floats, anything negative just means no fuel at all
+fn steps(fuel: Option<f64>) -> Option<u64> {
+    return fuel.map(|fuel| fuel.max(0.0) as u64);
+}
+
+// run_source hangs on to the sink it's given, and the errors still have to
+// land in it after the output, so it gets a handle instead of the real thing
+struct SharedSink {
+    sink: Shared<Box<dyn OutputSink>>,
+}
+
+impl OutputSink for SharedSink {
+    fn write(&mut self, chunk: &str) {
+        self.sink.get().write(chunk);
+    }
+
+    fn contents(&self) -> String {
+        return self.sink.get().contents();
+    }
+}
+
+fn run(input: &str, source: Box<dyn InputSource>, mut sink: Box<dyn OutputSink>, fuel: Option<u64>, includes: IncludeOptions) -> String{
+    sink.write("L I G M A  Interprets Generally Meaningless Abstractions\nv0.1.0\n\n");
+    let sink = Shared::new(sink);
+    let options = RunOptions {
+        fuel,
+        includes,
+        input: source,
+        output: Box::new(SharedSink { sink: sink.clone() }),
+        ..RunOptions::default()
+    };
+    let result = run_source(input, options);
+    let mut printer = sink.get();
+    for e in &result.errors {
+        if result.ran {
+            printer.write("\n\n");
+            printer.write(&e.render(input));
+        } else {
+            printer.write(&e.render(input));
+            printer.write("\n");
+        }
+    }
+    return printer.contents();
+}
+
+// What one Session::eval said, kept apart so the page can show errors
+// somewhere other than in the middle of the program's output.
+#[wasm_bindgen(getter_with_clone)]
+pub struct EvalResult {
+    pub output: String,
+    pub diagnostics: String,
+    pub ok: bool,
+}
+
+// One long running Fun for the playground REPL. Variables, functions, defs
+// and the argreg all stick around between evals until reset().
+//
+// This stays on the walker even though run_source doesn't. A Fun keeps all
+// of that by name from one eval to the next, the VM compiles every program
+// into slots of its own and has nothing left once it's done. The two mean the
+// same thing by every program (run.rs tests that), so a chunk comes out here
+// the way it would from run_source. The one difference is calls only go 100
+// deep, see InterpreterConfig::for_walker.
+#[wasm_bindgen]
+pub struct Session {
+    fun: Fun,
+    read_line: Option<js_sys::Function>,
+    fuel: Option<u64>,
+    includes: IncludeOptions,
+}
+
+#[wasm_bindgen]
+impl Session {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Session {
+        let mut session = Session {
+            fun: Fun::new(Vec::new(), InterpreterConfig::for_walker()),
+            read_line: None,
+            fuel: None,
+            includes: IncludeOptions::default(),
+        };
+        session.reset();
+        return session;
+    }
+
+    // ??name; asks this from now on, reset() keeps it
+    pub fn set_input(&mut self, read_line: js_sys::Function) {
+        self.fun.set_input(Box::new(JsInput { callback: read_line.clone() }));
+        self.read_line = Some(read_line);
+    }
+
+    // every eval gets this many steps, undefined takes the limit off again
+    pub fn set_fuel(&mut self, fuel: Option<f64>) {
+        self.fuel = steps(fuel);
+    }
+
+    // #[path] in a chunk reads from these from now on, reset() keeps them.
+    // Later changes to files aren't seen until this is called again.
+    pub fn set_files(&mut self, files: &Files) {
+        self.includes = files.includes(None);
+    }
+
+    pub fn reset(&mut self) {
+        self.fun = Fun::new(Vec::new(), InterpreterConfig::for_walker());
+        match &self.read_line {
+            Some(read_line) => {
+                self.fun.set_input(Box::new(JsInput { callback: read_line.clone() }));
+            }
+            None => {
+                self.fun.set_input(Box::new(QueueInput::new(Vec::new())));
+            }
+        }
+    }
+
+    pub fn eval(&mut self, chunk: String) -> EvalResult {
+        let mut result = EvalResult {
+            output: String::new(),
+            diagnostics: String::new(),
+            ok: false,
+        };
+        let program = match parse_source_with(&chunk, &self.includes, &InterpreterConfig::for_walker()) {
+            Ok(program) => program,
+            Err(errors) => {
+                for e in errors {
+                    result.diagnostics.push_str(&e.render(&chunk));
+                    result.diagnostics.push('\n');
                 }
+                return result;
+            }
+        };
+        // a fresh sink per chunk, so output only ever holds this chunk's
+        self.fun.set_output(Box::new(CappedSink::new(OUTPUT_LIMIT)));
+        self.fun.set_fuel(self.fuel);
+        match self.fun.eval_chunk(program) {
+            Ok(()) => {
+                result.ok = true;
+            }
+            Err(e) => {
+                result.diagnostics = e.render(&chunk);
+            }
+        }
+        result.output = self.fun.get_output();
+        return result;
+    }
+
+    // undefined if there's no such variable or it holds nothing JS can use
+    pub fn get_var(&self, name: &str) -> JsValue {
+        match self.fun.get_var(&None, name) {
+            Ok(exp) => {
+                return to_js(exp);
             }
-            _ => {
-                tokens.push(token);
+            Err(_) => {
+                return JsValue::UNDEFINED;
             }
         }
-        
     }
-    let mut fun = Fun::new(tokens);
-    fun.eval();
-    let mut output = String::from("L I G M A  Interprets Generally Meaningless Abstractions\nv0.1.0\n\n");
-    let printer = fun.get_output();
-    {
-        let printer = printer.lock().unwrap();
-        output.push_str(&printer);
+
+    pub fn set_var(&mut self, name: &str, value: JsValue) -> Result<(), JsValue> {
+        match from_js(&value) {
+            Some(exp) => {
+                return self.fun.set_var(None, name, exp).map_err(|e| JsValue::from_str(&e.to_string()));
+            }
+            None => {
+                let msg = format!("`{}` can't hold that, only booleans, numbers, strings, arrays and plain objects", name);
+                return Err(JsValue::from_str(&msg));
+            }
+        }
+    }
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        return Session::new();
+    }
+}
+
+fn to_js(exp: Expr) -> JsValue {
+    match exp {
+        Expr::Bool(t) => JsValue::from_bool(t),
+        Expr::Int(t) => JsValue::from_f64(t as f64),
+        Expr::Float(t) => JsValue::from_f64(t),
+        Expr::String(t) => JsValue::from_str(&t),
+        Expr::List(items) => {
+            let array = js_sys::Array::new();
+            for item in items {
+                array.push(&to_js(item));
+            }
+            array.into()
+        }
+        Expr::Map(entries) => {
+            let object = js_sys::Object::new();
+            for (key, value) in entries {
+                let _ = js_sys::Reflect::set(&object, &JsValue::from_str(&key), &to_js(value));
+            }
+            object.into()
+        }
+        _ => JsValue::UNDEFINED,
     }
+}
 
-    return output;
+// JS only has one kind of number, whole ones come back as Int
+fn from_js(value: &JsValue) -> Option<Expr> {
+    if let Some(t) = value.as_bool() {
+        return Some(Expr::Bool(t));
+    }
+    if let Some(t) = value.as_f64() {
+        if t.fract() == 0.0 && t.abs() < i64::MAX as f64 {
+            return Some(Expr::Int(t as i64));
+        }
+        return Some(Expr::Float(t));
+    }
+    if let Some(t) = value.as_string() {
+        return Some(Expr::String(t));
+    }
+    if js_sys::Array::is_array(value) {
+        let mut items: Vec<Expr> = Vec::new();
+        for item in js_sys::Array::from(value).iter() {
+            items.push(from_js(&item)?);
+        }
+        return Some(Expr::List(items));
+    }
+    if value.is_object() {
+        let mut entries = std::collections::BTreeMap::new();
+        for entry in js_sys::Object::entries(&js_sys::Object::from(value.clone())).iter() {
+            let entry = js_sys::Array::from(&entry);
+            let key = entry.get(0).as_string()?;
+            entries.insert(key, from_js(&entry.get(1))?);
+        }
+        return Some(Expr::Map(entries));
+    }
+    return None;
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what the playground would send one line at a time
+    const CHUNKS: [&str; 6] = [
+        "x = 7; !x;",
+        "sq(n){ @@ n * n; }",
+        "#<twice>{ x = x * 2; }",
+        "#(twice) y = sq(x); !y;",
+        "xs = [1, 2]; push(xs, y); !xs;",
+        "@ = \"reg\"; r = @; !r; ?(y > 100){ !\"big\"; }",
+    ];
+
+    #[test]
+    fn session_agrees_with_the_bytecode_engine() {
+        let mut session = Session::new();
+        let mut output = String::new();
+        for chunk in CHUNKS {
+            let result = session.eval(chunk.to_string());
+            assert!(result.ok, "{} went wrong: {}", chunk, result.diagnostics);
+            output.push_str(&result.output);
+        }
+        let whole = run_source(&CHUNKS.join("\n"), RunOptions::default());
+        assert!(whole.ok(), "{:?}", whole.errors);
+        assert_eq!(output, whole.output);
+    }
+
+    #[test]
+    fn session_errors_like_the_bytecode_engine() {
+        let mut session = Session::new();
+        assert!(session.eval(String::from("a = [1];")).ok);
+        let result = session.eval(String::from("b = a[3];"));
+        let whole = run_source("a = [1];\nb = a[3];", RunOptions::default());
+        assert!(!result.ok);
+        assert!(result.diagnostics.contains(&whole.errors[0].message), "{}", result.diagnostics);
+        // and the session is still there afterwards
+        assert!(session.eval(String::from("!a;")).ok);
+    }
+
+    #[test]
+    fn session_runs_out_of_calls_before_it_runs_out_of_stack() {
+        // what a debug wasm build gets, see .cargo/config.toml
+        let running = std::thread::Builder::new().stack_size(16 * 1024 * 1024).spawn(|| {
+            let mut session = Session::new();
+            let result = session.eval(String::from("r(n){ @@ r(n + 1); } x = r(0);"));
+            return (result.ok, result.diagnostics);
+        });
+        let (ok, diagnostics) = running.unwrap().join().unwrap();
+        assert!(!ok);
+        assert!(diagnostics.contains("deeper than 100 frames"), "{}", diagnostics);
+    }
+}
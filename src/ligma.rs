@@ -1,4 +1,16 @@
 pub mod lexer;
+pub mod include;
+pub mod files;
 pub mod fun;
+pub mod compile;
+pub mod vm;
 pub mod expr;
+pub mod stmt;
+pub mod parser;
 pub mod serendipity;
+pub mod input;
+pub mod output;
+pub mod config;
+pub mod shared;
+pub mod fmt;
+pub mod run;
@@ -0,0 +1,701 @@
+use std::collections::HashMap;
+use crate::Expr;
+use crate::Token;
+use crate::ligma::config::InterpreterConfig;
+use crate::ligma::serendipity::{ErrorKind, LigmaError, Span};
+use crate::ligma::stmt::{DefBody, Stmt};
+
+// One instruction for the Vm. Anything bigger than a number lives in a table
+// on the Chunk and the op just says where, so ops can be copied out for free.
+// The stack holds Expr values, "pops x" below means off the top of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    // steps of fuel, one for everywhere Fun::burn would take one. Nothing
+    // anyone can see happens between two burns in a row, so they're added up.
+    Burn(usize),
+    Const(usize),
+    Pop,
+    Dup,
+    Swap,
+    // slot, and the span a miss points at (None leaves it to the statement)
+    Load(usize, Option<usize>),
+    // same, pops the index first
+    LoadIndexed(usize, Option<usize>),
+    Store(usize),
+    // pops the value, then the index
+    StoreIndexed(usize),
+    Kill(usize),
+    LoadArgreg(usize),
+    LoadArgregIndexed(usize),
+    StoreArgreg,
+    StoreArgregIndexed,
+    // into operators, and where each side comes from
+    BinOp(u32, Operand, Operand),
+    // into tokens
+    UnOp(usize),
+    // how many values to pop
+    List(usize),
+    // how many key, value pairs to pop
+    Map(usize),
+    // pops the key then the target, span for a bad index
+    Index(usize),
+    // errors like Load would if the slot is empty, without copying anything out
+    Need(usize, usize),
+    // Index on a slot where it lives, pops the key
+    IndexSlot(usize, usize),
+    // errors like Load(slot, None) would, without copying anything out. With
+    // true the index is on top of the stack and stays there.
+    Have(usize, bool),
+    // slot, whether there's an index under the keys, how many keys. Pops the
+    // value, the keys and the index and puts the value in where the slot's
+    // list or map lives.
+    SetIndex(usize, bool, usize),
+    Jump(usize),
+    // pops the condition
+    JumpUnless(usize),
+    // pops a list, map or string and starts handing out its items
+    EachStart,
+    // the loop variable's slot, and where to go once there's nothing left
+    EachNext(usize, usize),
+    // forgets the innermost each, for $; leaving one early
+    EachEnd,
+    Output,
+    // into texts
+    OutputStr(usize),
+    // pushes a line, the slot is only there to name it when there isn't one
+    Input(usize),
+    // into the program's functions
+    Function(usize),
+    // into defs
+    Def(usize),
+    // into inserts, and where a $; inside it ends up
+    Insert(usize, Option<usize>),
+    // a $; inside #(def) code with no loop of its own, span of the $;
+    BreakOut(usize),
+    // call site, and where the builtin version of the call starts when there's
+    // no function by that name
+    FindCall(usize, usize),
+    // pops an argument
+    Arg,
+    // a bare name as an argument hands over its whole [index] map, slot and span
+    ArgVar(usize, usize),
+    // call site, and whether whoever called it wants a value back
+    Call(usize, bool),
+    Len,
+    // Len on a slot where it lives, and the span for a missing variable
+    LenSlot(usize, usize),
+    // slot, whether the index is on the stack, call site. Checks there's a
+    // list there before push works out what goes on it.
+    ListCheck(usize, bool, usize),
+    // same, the index is under the value, both get popped
+    ListPush(usize, bool, usize),
+    ListPop(usize, bool, usize),
+    // whether there's a value to pop
+    Return(bool),
+    // into errors, for things that were always going to go wrong
+    Fail(usize),
+}
+
+// Where a BinOp gets one side from. A plain name or a literal doesn't need a
+// Load or Const of its own first, anything else was worked out onto the stack.
+// The left side is only ever read here when the right one is too, so nothing
+// gets looked at in a different order than Fun would.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Stack,
+    // slot, and the span a miss points at
+    Slot(u32, u32),
+    Const(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    pub name: String,
+    pub args: usize,
+    pub span: Span,
+}
+
+// One function's worth of code, or the top level, or one #(def).
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub name: String,
+    // where each argument goes, in order
+    pub params: Vec<usize>,
+    // how many parameters it was declared with, 0 for name{ }
+    pub arity: usize,
+    pub code: Vec<Op>,
+    // which statement every op came from, into spans
+    pub lines: Vec<usize>,
+    pub spans: Vec<Span>,
+    pub consts: Vec<Expr>,
+    // every variable gets a slot per frame, names[slot] is what it was called
+    pub names: Vec<String>,
+    pub tokens: Vec<Token>,
+    // binary operators, with where they are into spans for when one fails
+    pub operators: Vec<(Token, usize)>,
+    pub texts: Vec<Vec<String>>,
+    pub calls: Vec<CallSite>,
+    pub defs: Vec<(String, DefBody)>,
+    pub inserts: Vec<String>,
+    pub errors: Vec<LigmaError>,
+}
+
+// The top level plus every function declared anywhere in it.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub main: Chunk,
+    pub functions: Vec<Chunk>,
+}
+
+impl Program {
+    // what `ligma bytecode` prints
+    pub fn disassemble(&self) -> String {
+        let mut text = self.main.disassemble();
+        for function in &self.functions {
+            text.push('\n');
+            text.push_str(&function.disassemble());
+        }
+        return text;
+    }
+}
+
+impl Chunk {
+    pub fn disassemble(&self) -> String {
+        let mut text = format!("== {} ==\n", self.name);
+        for (at, op) in self.code.iter().enumerate() {
+            let line = self.spans[self.lines[at]].line;
+            let note = match op {
+                Op::Const(i) => format!("{:?}", self.consts[*i]),
+                Op::Load(slot, _) | Op::LoadIndexed(slot, _) | Op::Store(slot) | Op::StoreIndexed(slot) |
+                Op::Kill(slot) | Op::EachNext(slot, _) | Op::Input(slot) | Op::ArgVar(slot, _) |
+                Op::ListCheck(slot, _, _) | Op::ListPush(slot, _, _) | Op::ListPop(slot, _, _) |
+                Op::Need(slot, _) | Op::IndexSlot(slot, _) | Op::LenSlot(slot, _) => self.names[*slot].clone(),
+                Op::BinOp(i, _, _) => format!("{:?}", self.operators[*i as usize].0),
+                Op::UnOp(i) => format!("{:?}", self.tokens[*i]),
+                Op::OutputStr(i) => format!("{:?}", self.texts[*i]),
+                Op::FindCall(site, _) | Op::Call(site, _) => self.calls[*site].name.clone(),
+                Op::Def(i) => self.defs[*i].0.clone(),
+                Op::Insert(i, _) => self.inserts[*i].clone(),
+                Op::Fail(i) => self.errors[*i].message.clone(),
+                _ => String::new(),
+            };
+            text.push_str(&format!("{:>5} {:>5}  {:?}", at, line, op));
+            if note.len() > 0 {
+                text.push_str(&format!("  ; {}", note));
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+}
+
+// A chunk on its way to being finished.
+struct Builder {
+    chunk: Chunk,
+    slots: HashMap<String, usize>,
+    // for every loop we're inside, the ops a $; has to be pointed at its end
+    loops: Vec<Vec<usize>>,
+    // the statement being compiled, into spans
+    line: usize,
+    // #(def) code, a $; with no loop here belongs to whatever inserted it
+    inserted: bool,
+    // the last place something jumps to, a Burn there can't be added to the one before
+    label: Option<usize>,
+}
+
+impl Builder {
+    fn new(name: &str, names: Vec<String>, inserted: bool) -> Builder {
+        let mut builder = Builder {
+            chunk: Chunk::default(),
+            slots: HashMap::new(),
+            loops: Vec::new(),
+            line: 0,
+            inserted,
+            label: None,
+        };
+        builder.chunk.name = name.to_string();
+        for name in names {
+            builder.slot(&name);
+        }
+        return builder;
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let here = self.here();
+        if let (Op::Burn(steps), Some(Op::Burn(before))) = (op, self.chunk.code.last_mut()) {
+            if self.label != Some(here) {
+                *before += steps;
+                return here - 1;
+            }
+        }
+        self.chunk.code.push(op);
+        self.chunk.lines.push(self.line);
+        return self.chunk.code.len() - 1;
+    }
+
+    fn here(&self) -> usize {
+        return self.chunk.code.len();
+    }
+
+    // here, as somewhere a jump is going to land
+    fn label(&mut self) -> usize {
+        self.label = Some(self.here());
+        return self.here();
+    }
+
+    // points a jump made before we knew where to at here
+    fn patch(&mut self, at: usize) {
+        let to = self.label();
+        match &mut self.chunk.code[at] {
+            Op::Jump(target) | Op::JumpUnless(target) | Op::EachNext(_, target) | Op::FindCall(_, target) => {
+                *target = to;
+            }
+            Op::Insert(_, target) => {
+                *target = Some(to);
+            }
+            _ => {}
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.slots.get(name) {
+            return *slot;
+        }
+        self.chunk.names.push(name.to_string());
+        self.slots.insert(name.to_string(), self.chunk.names.len() - 1);
+        return self.chunk.names.len() - 1;
+    }
+
+    fn span(&mut self, span: &Span) -> usize {
+        self.chunk.spans.push(span.clone());
+        return self.chunk.spans.len() - 1;
+    }
+
+    fn constant(&mut self, value: Expr) -> usize {
+        self.chunk.consts.push(value);
+        return self.chunk.consts.len() - 1;
+    }
+
+    fn token(&mut self, token: &Token) -> usize {
+        self.chunk.tokens.push(token.clone());
+        return self.chunk.tokens.len() - 1;
+    }
+
+    fn operator(&mut self, token: &Token, span: &Span) -> usize {
+        let span = self.span(span);
+        self.chunk.operators.push((token.clone(), span));
+        return self.chunk.operators.len() - 1;
+    }
+
+    fn fail(&mut self, error: LigmaError) {
+        self.chunk.errors.push(error);
+        let at = self.chunk.errors.len() - 1;
+        self.emit(Op::Fail(at));
+    }
+}
+
+// Turns statements into Chunks. Everything Fun would only find out while
+// running, like which function a name means, is still left to the Vm, so
+// redefining things halfway through works the same.
+pub struct Compiler<'a> {
+    config: &'a InterpreterConfig,
+    functions: Vec<Chunk>,
+    // how many functions the Vm has already, new ones are numbered after those
+    base: usize,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(config: &'a InterpreterConfig, base: usize) -> Compiler<'a> {
+        Compiler {
+            config,
+            functions: Vec::new(),
+            base,
+        }
+    }
+
+    pub fn compile(mut self, stmts: &[Stmt]) -> Program {
+        let mut builder = Builder::new("<main>", Vec::new(), false);
+        self.block(&mut builder, stmts);
+        return Program {
+            main: builder.chunk,
+            functions: self.functions,
+        };
+    }
+
+    // #(def) code runs in the frame that inserted it, so it gets the same slots
+    // for the same names and anything new goes after them
+    pub fn compile_insert(mut self, name: &str, stmts: &[Stmt], names: Vec<String>) -> Program {
+        let mut builder = Builder::new(&format!("#({})", name), names, true);
+        self.block(&mut builder, stmts);
+        return Program {
+            main: builder.chunk,
+            functions: self.functions,
+        };
+    }
+
+    fn function(&mut self, name: &str, params: &[String], body: &[Stmt]) -> usize {
+        // name{ } only ever gets _@
+        let names = if params.len() == 0 { vec![String::from("_@")] } else { Vec::new() };
+        let mut builder = Builder::new(name, names, false);
+        if params.len() == 0 {
+            builder.chunk.params = vec![0];
+        }
+        for param in params {
+            let slot = builder.slot(param);
+            builder.chunk.params.push(slot);
+        }
+        builder.chunk.arity = params.len();
+        self.block(&mut builder, body);
+        self.functions.push(builder.chunk);
+        return self.base + self.functions.len() - 1;
+    }
+
+    fn block(&mut self, b: &mut Builder, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(b, stmt);
+        }
+    }
+
+    fn stmt(&mut self, b: &mut Builder, stmt: &Stmt) {
+        b.line = b.span(stmt.span());
+        b.emit(Op::Burn(1));
+        match stmt {
+            Stmt::Assign(index, name, exp, _) => {
+                let slot = b.slot(name);
+                if let Some(index) = index {
+                    self.exp(b, index, 0);
+                    self.exp(b, exp, 0);
+                    b.emit(Op::StoreIndexed(slot));
+                } else {
+                    self.exp(b, exp, 0);
+                    b.emit(Op::Store(slot));
+                }
+            }
+            Stmt::Argreg(index, exp, _) => {
+                if let Some(index) = index {
+                    self.exp(b, index, 0);
+                    self.exp(b, exp, 0);
+                    b.emit(Op::StoreArgregIndexed);
+                } else {
+                    self.exp(b, exp, 0);
+                    b.emit(Op::StoreArgreg);
+                }
+            }
+            Stmt::SetIndex(index, name, keys, exp, _) => {
+                let slot = b.slot(name);
+                if let Some(index) = index {
+                    self.exp(b, index, 0);
+                }
+                b.emit(Op::Have(slot, index.is_some()));
+                for key in keys {
+                    self.exp(b, key, 0);
+                }
+                self.exp(b, exp, 0);
+                b.emit(Op::SetIndex(slot, index.is_some(), keys.len()));
+            }
+            Stmt::If(cond, body, otherwise, _) => {
+                self.exp(b, cond, 0);
+                let skip = b.emit(Op::JumpUnless(0));
+                self.block(b, body);
+                if let Some(otherwise) = otherwise {
+                    let end = b.emit(Op::Jump(0));
+                    b.patch(skip);
+                    self.block(b, otherwise);
+                    b.patch(end);
+                } else {
+                    b.patch(skip);
+                }
+            }
+            Stmt::Loop(cond, body, _) => {
+                let top = b.label();
+                b.emit(Op::Burn(1));
+                self.exp(b, cond, 0);
+                let done = b.emit(Op::JumpUnless(0));
+                b.loops.push(Vec::new());
+                self.block(b, body);
+                b.emit(Op::Jump(top));
+                b.patch(done);
+                for at in b.loops.pop().unwrap_or_default() {
+                    b.patch(at);
+                }
+            }
+            Stmt::Each(name, collection, body, _) => {
+                let slot = b.slot(name);
+                self.exp(b, collection, 0);
+                b.emit(Op::EachStart);
+                let top = b.label();
+                b.emit(Op::EachNext(slot, 0));
+                b.loops.push(Vec::new());
+                self.block(b, body);
+                b.emit(Op::Jump(top));
+                // a $; still has an item list to throw away, running out doesn't
+                for at in b.loops.pop().unwrap_or_default() {
+                    b.patch(at);
+                }
+                b.emit(Op::EachEnd);
+                b.patch(top);
+            }
+            Stmt::Break(span) => {
+                if b.loops.len() > 0 {
+                    let at = b.emit(Op::Jump(0));
+                    b.loops.last_mut().unwrap().push(at);
+                } else if b.inserted {
+                    let span = b.span(span);
+                    b.emit(Op::BreakOut(span));
+                } else {
+                    b.fail(LigmaError::unexpected("`$;` has no loop to break out of").with_span(span.clone()));
+                }
+            }
+            Stmt::Output(exp, _) => {
+                self.exp(b, exp, 0);
+                b.emit(Op::Output);
+            }
+            Stmt::OutputStr(strings, _) => {
+                b.chunk.texts.push(strings.clone());
+                let at = b.chunk.texts.len() - 1;
+                b.emit(Op::OutputStr(at));
+            }
+            Stmt::Input(index, name, _) => {
+                // the line is read before the index is worked out
+                let slot = b.slot(name);
+                b.emit(Op::Input(slot));
+                if let Some(index) = index {
+                    self.exp(b, index, 0);
+                    b.emit(Op::Swap);
+                    b.emit(Op::StoreIndexed(slot));
+                } else {
+                    b.emit(Op::Store(slot));
+                }
+            }
+            Stmt::Kill(name, _) => {
+                let slot = b.slot(name);
+                b.emit(Op::Kill(slot));
+            }
+            Stmt::Function(name, params, body, _) => {
+                let function = self.function(name, params, body);
+                b.emit(Op::Function(function));
+            }
+            Stmt::Call(name, args, span) => {
+                self.call(b, name, args, span, 0, false);
+            }
+            Stmt::Return(exp, _) => {
+                if let Some(exp) = exp {
+                    self.exp(b, exp, 0);
+                    b.emit(Op::Return(true));
+                } else {
+                    b.emit(Op::Return(false));
+                }
+            }
+            Stmt::Def(name, body, _) => {
+                b.chunk.defs.push((name.clone(), body.clone()));
+                let at = b.chunk.defs.len() - 1;
+                b.emit(Op::Def(at));
+            }
+            Stmt::Insert(name, _) => {
+                b.chunk.inserts.push(name.clone());
+                let at = b.chunk.inserts.len() - 1;
+                let insert = b.emit(Op::Insert(at, None));
+                if let Some(breaks) = b.loops.last_mut() {
+                    breaks.push(insert);
+                }
+            }
+        }
+    }
+
+    // depth is how many eval_exp calls deep Fun would be when it got here
+    fn exp(&mut self, b: &mut Builder, exp: &Expr, depth: usize) {
+        if depth >= self.config.max_expr_nesting {
+            let msg = format!("expression nests deeper than {} levels", self.config.max_expr_nesting);
+            b.fail(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            return;
+        }
+        match exp {
+            Expr::Var(index, name, span) => {
+                let slot = b.slot(name);
+                let span = b.span(span);
+                if let Some(index) = index {
+                    self.exp(b, index, depth + 1);
+                    b.emit(Op::LoadIndexed(slot, Some(span)));
+                } else {
+                    b.emit(Op::Load(slot, Some(span)));
+                }
+            }
+            Expr::Argreg(index, span) => {
+                let span = b.span(span);
+                if let Some(index) = index {
+                    self.exp(b, index, depth + 1);
+                    b.emit(Op::LoadArgregIndexed(span));
+                } else {
+                    b.emit(Op::LoadArgreg(span));
+                }
+            }
+            Expr::Call(name, args, span) => {
+                self.call(b, name, args, span, depth + 1, true);
+            }
+            Expr::UnOp(op, exp) => {
+                self.exp(b, exp, depth + 1);
+                let op = b.token(op);
+                b.emit(Op::UnOp(op));
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.exp(b, item, depth + 1);
+                }
+                b.emit(Op::List(items.len()));
+            }
+            Expr::MapLit(entries) => {
+                for (key, value) in entries {
+                    self.exp(b, key, depth + 1);
+                    self.exp(b, value, depth + 1);
+                }
+                b.emit(Op::Map(entries.len()));
+            }
+            Expr::Index(target, key, span) => {
+                // a plain name gets indexed where it lives, see Fun::eval_exp_inner
+                if let (Expr::Var(None, name, var_span), true) = (&**target, depth + 1 < self.config.max_expr_nesting) {
+                    let slot = b.slot(name);
+                    let var_span = b.span(var_span);
+                    b.emit(Op::Need(slot, var_span));
+                    self.exp(b, key, depth + 1);
+                    let span = b.span(span);
+                    b.emit(Op::IndexSlot(slot, span));
+                    return;
+                }
+                self.exp(b, target, depth + 1);
+                self.exp(b, key, depth + 1);
+                let span = b.span(span);
+                b.emit(Op::Index(span));
+            }
+            Expr::BinOp(exp_a, op, exp_b, span) => {
+                b.emit(Op::Burn(1));
+                let mut left = Operand::Stack;
+                let right = match self.operand(b, exp_b, depth + 1) {
+                    Some(right) => {
+                        match self.operand(b, exp_a, depth + 1) {
+                            Some(operand) => {
+                                left = operand;
+                            }
+                            None => {
+                                self.exp(b, exp_a, depth + 1);
+                            }
+                        }
+                        right
+                    }
+                    None => {
+                        self.exp(b, exp_a, depth + 1);
+                        self.exp(b, exp_b, depth + 1);
+                        Operand::Stack
+                    }
+                };
+                let op = b.operator(op, span) as u32;
+                b.emit(Op::BinOp(op, left, right));
+            }
+            _ => {
+                let value = b.constant(exp.clone());
+                b.emit(Op::Const(value));
+            }
+        }
+    }
+
+    // a side of a BinOp that can be read straight from a slot or the constants,
+    // None for anything that needs code of its own, including going too deep
+    fn operand(&mut self, b: &mut Builder, exp: &Expr, depth: usize) -> Option<Operand> {
+        if depth >= self.config.max_expr_nesting {
+            return None;
+        }
+        match exp {
+            Expr::Var(None, name, span) => {
+                let slot = b.slot(name) as u32;
+                let span = b.span(span) as u32;
+                return Some(Operand::Slot(slot, span));
+            }
+            Expr::Bool(_) | Expr::Int(_) | Expr::Float(_) | Expr::String(_) => {
+                let value = b.constant(exp.clone()) as u32;
+                return Some(Operand::Const(value));
+            }
+            _ => {
+                return None;
+            }
+        }
+    }
+
+    // Whether name means a function or a builtin is only known once it runs, so
+    // both get compiled and FindCall picks. Arguments sit at depth, which is 0
+    // for a call that's a statement on its own.
+    fn call(&mut self, b: &mut Builder, name: &str, args: &[Expr], span: &Span, depth: usize, want: bool) {
+        b.chunk.calls.push(CallSite {
+            name: name.to_string(),
+            args: args.len(),
+            span: span.clone(),
+        });
+        let site = b.chunk.calls.len() - 1;
+        let find = b.emit(Op::FindCall(site, 0));
+        for arg in args {
+            if let Expr::Var(None, name, span) = arg {
+                let slot = b.slot(name);
+                let span = b.span(span);
+                b.emit(Op::ArgVar(slot, span));
+            } else {
+                self.exp(b, arg, depth);
+                b.emit(Op::Arg);
+            }
+        }
+        b.emit(Op::Call(site, want));
+        let end = b.emit(Op::Jump(0));
+        b.patch(find);
+        self.builtin(b, name, args, site, depth);
+        if !want {
+            b.emit(Op::Pop);
+        }
+        b.patch(end);
+    }
+
+    // len(a), push(list, value) and pop(list), see Fun::call_builtin
+    fn builtin(&mut self, b: &mut Builder, name: &str, args: &[Expr], site: usize, depth: usize) {
+        let arity = match name {
+            "len" | "pop" => 1,
+            "push" => 2,
+            _ => {
+                b.fail(LigmaError::undefined_function(name));
+                return;
+            }
+        };
+        if args.len() != arity {
+            let msg = format!("`{}` takes {} argument(s) but was given {}", name, arity, args.len());
+            b.fail(LigmaError::new(ErrorKind::ArgumentCount, msg));
+            return;
+        }
+        if name == "len" {
+            if let (Expr::Var(None, var, span), true) = (&args[0], depth < self.config.max_expr_nesting) {
+                let slot = b.slot(var);
+                let span = b.span(span);
+                b.emit(Op::LenSlot(slot, span));
+                return;
+            }
+            self.exp(b, &args[0], depth);
+            b.emit(Op::Len);
+            return;
+        }
+        let (slot, indexed) = match &args[0] {
+            Expr::Var(index, var, _) => {
+                let slot = b.slot(var);
+                if let Some(index) = index {
+                    self.exp(b, index, depth);
+                }
+                (slot, index.is_some())
+            }
+            _ => {
+                let msg = format!("`{}` needs a variable holding a list", name);
+                b.fail(LigmaError::new(ErrorKind::TypeMismatch, msg));
+                return;
+            }
+        };
+        if name == "push" {
+            b.emit(Op::ListCheck(slot, indexed, site));
+            self.exp(b, &args[1], depth);
+            b.emit(Op::ListPush(slot, indexed, site));
+        } else {
+            b.emit(Op::ListPop(slot, indexed, site));
+        }
+    }
+}
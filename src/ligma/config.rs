@@ -0,0 +1,41 @@
+// Everything Fun refuses to go past. The first two are what keeps a
+// recursive program from blowing the real stack (which in wasm just kills
+// the page), so they always have a limit. max_expr_nesting is checked while
+// parsing too, for blocks as well as expressions, since the parser recurses
+// just the same. The rest are None unless you want one, apart from strings.
+#[derive(Debug, Clone)]
+pub struct InterpreterConfig {
+    pub max_call_depth: usize,
+    pub max_expr_nesting: usize,
+    pub max_string_length: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    // per function frame, the top level counts as one
+    pub max_variables: Option<usize>,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> InterpreterConfig {
+        InterpreterConfig {
+            max_call_depth: 300,
+            max_expr_nesting: 300,
+            // "ab" * 3000000000000 asks for 6TB and takes the whole process
+            // down with it, 16MB is more text than anyone wants printed
+            max_string_length: Some(16 * 1024 * 1024),
+            max_output_bytes: None,
+            max_variables: None,
+        }
+    }
+}
+
+impl InterpreterConfig {
+    // Fun keeps every ligma call on the real stack, around 40KB of it a call
+    // in a debug build and well over twice that when the call sits a few
+    // blocks in. A Session gets the 16MB wasm stack and nothing else, so
+    // anything running the walker on a stack it didn't pick goes 100 deep.
+    pub fn for_walker() -> InterpreterConfig {
+        return InterpreterConfig {
+            max_call_depth: 100,
+            ..InterpreterConfig::default()
+        };
+    }
+}
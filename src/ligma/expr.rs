@@ -1,13 +1,27 @@
 use crate::Token;
+use crate::ligma::serendipity::Span;
+use std::collections::BTreeMap;
 //TODO seperate exp functions from Fun to Expr impl
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     New,
     Bool(bool),
     Int(i64),
     Float(f64),
     String(String),
-    BinOp(Box<Expr>, Token, Box<Expr>),
+    BinOp(Box<Expr>, Token, Box<Expr>, Span),
+    // [a, b, c] -- also the literal, Fun::eval_exp evaluates whatever is inside
+    List(Vec<Expr>),
+    // keys get stringified like the old [i] indexes, sorted so printing is stable
+    Map(BTreeMap<String, Expr>),
+
+    // these only come out of the parser, Fun::eval_exp turns them into values
+    Var(Option<Box<Expr>>, String, Span), // [index]name
+    Argreg(Option<Box<Expr>>, Span), // [index]@
+    Call(String, Vec<Expr>, Span), // name(arg, arg)
+    UnOp(Token, Box<Expr>), // -a, +a, ~a
+    MapLit(Vec<(Expr, Expr)>), // {key: value, key: value}
+    Index(Box<Expr>, Box<Expr>, Span), // a[key]
 }
 impl Expr {
     pub fn parse_exp_string(exp: Expr) -> String {
@@ -33,11 +47,33 @@ impl Expr {
             Expr::String(t) => {
                 return t;
             }
+            Expr::List(items) => {
+                let items: Vec<String> = items.into_iter().map(Expr::parse_exp_element).collect();
+                string = format!("[{}]", items.join(", "));
+            }
+            Expr::Map(entries) => {
+                let entries: Vec<String> = entries.into_iter()
+                    .map(|(key, value)| format!("{:?}: {}", key, Expr::parse_exp_element(value)))
+                    .collect();
+                string = format!("{{{}}}", entries.join(", "));
+            }
             _ => {
             }
         }
         return string;
     }
+
+    // inside a list a string gets its quotes back, otherwise ["1"] and [1] look the same
+    fn parse_exp_element(exp: Expr) -> String {
+        match exp {
+            Expr::String(t) => {
+                return format!("{:?}", t);
+            }
+            _ => {
+                return Expr::parse_exp_string(exp);
+            }
+        }
+    }
     pub fn parse_exp_float(exp: Expr) -> f64 {
         let mut float: f64 = 420.0;
         match exp{
@@ -83,6 +119,12 @@ impl Expr {
             Expr::String(t) => {
                 integer = t.len() as i64;
             }
+            Expr::List(t) => {
+                integer = t.len() as i64;
+            }
+            Expr::Map(t) => {
+                integer = t.len() as i64;
+            }
             _ => {
             }
         }
@@ -116,6 +158,12 @@ impl Expr {
                     boolean = true;
                 }
             }
+            Expr::List(t) => {
+                boolean = t.len() > 0;
+            }
+            Expr::Map(t) => {
+                boolean = t.len() > 0;
+            }
             _ => {
             }
         }
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Where #[path] reads from, looked up by path. The CLI has the disk, the
+// browser and the tests have whatever they were handed.
+pub trait FileProvider: Send + Sync {
+    fn exists(&self, path: &Path) -> bool;
+    fn read(&self, path: &Path) -> Result<String, String>;
+
+    // one name per file however it was spelled, for include-once and cycles
+    fn canonical(&self, path: &Path) -> PathBuf;
+}
+
+impl fmt::Debug for dyn FileProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileProvider")
+    }
+}
+
+// The real filesystem, what the CLI uses.
+pub struct DiskFiles;
+
+impl FileProvider for DiskFiles {
+    fn exists(&self, path: &Path) -> bool {
+        return path.is_file();
+    }
+
+    fn read(&self, path: &Path) -> Result<String, String> {
+        return fs::read_to_string(path).map_err(|e| e.to_string());
+    }
+
+    fn canonical(&self, path: &Path) -> PathBuf {
+        return fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    }
+}
+
+// Files handed over up front by name, for the playground and for runs that
+// shouldn't care what's lying around on disk. There are no directories as
+// such, lib/x.ligma is just a name with a slash in it, and a leading / is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFiles {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> MemoryFiles {
+        MemoryFiles {
+            files: HashMap::new(),
+        }
+    }
+
+    // replaces whatever was there under that name
+    pub fn add(&mut self, path: &str, source: &str) {
+        self.files.insert(normalize(Path::new(path)), source.to_string());
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        return self.files.remove(&normalize(Path::new(path))).is_some();
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.files.keys().map(|path| path.display().to_string()).collect();
+        names.sort();
+        return names;
+    }
+}
+
+impl FileProvider for MemoryFiles {
+    fn exists(&self, path: &Path) -> bool {
+        return self.files.contains_key(&normalize(path));
+    }
+
+    fn read(&self, path: &Path) -> Result<String, String> {
+        match self.files.get(&normalize(path)) {
+            Some(source) => Ok(source.clone()),
+            None => Err(String::from("no such file")),
+        }
+    }
+
+    fn canonical(&self, path: &Path) -> PathBuf {
+        return normalize(path);
+    }
+}
+
+// lib/./x and lib/../lib/x are both lib/x, without asking a disk
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                normal.push(part);
+            }
+            Component::ParentDir => {
+                normal.pop();
+            }
+            _ => {}
+        }
+    }
+    return normal;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::ligma::include::IncludeOptions;
+    use crate::ligma::run::{run_with_lines, RunOptions};
+    use crate::ligma::serendipity::ErrorKind;
+
+    // main.ligma is what's being run, everything it includes comes out of files
+    fn from(files: MemoryFiles) -> RunOptions {
+        return RunOptions {
+            includes: IncludeOptions {
+                path: Some(PathBuf::from("main.ligma")),
+                files: Arc::new(files),
+                ..IncludeOptions::default()
+            },
+            ..RunOptions::default()
+        };
+    }
+
+    #[test]
+    fn nested_includes_come_out_of_memory() {
+        let mut files = MemoryFiles::new();
+        // next to the file doing the including, so this is lib/b.ligma
+        files.add("lib/a.ligma", "#[b.ligma] !\"a \";");
+        files.add("/lib/b.ligma", "!\"b \";");
+        let result = run_with_lines("#[lib/a.ligma] #[lib/b.ligma] !\"main\";", &[], from(files));
+        assert!(result.ok(), "{:?}", result.errors);
+        // b only gets pulled in the once
+        assert_eq!(result.output, "b a main");
+    }
+
+    #[test]
+    fn include_cycles_come_out_of_memory_too() {
+        let mut files = MemoryFiles::new();
+        files.add("a.ligma", "#[lib/b.ligma]");
+        files.add("lib/b.ligma", "#[../a.ligma]");
+        let result = run_with_lines("#[a.ligma]", &[], from(files));
+        assert!(!result.ran);
+        assert_eq!(result.errors[0].kind, ErrorKind::Include);
+        assert_eq!(result.errors[0].message, "include cycle: a.ligma -> lib/b.ligma -> a.ligma");
+    }
+
+    #[test]
+    fn missing_includes_say_which() {
+        let files = MemoryFiles::new();
+        let result = run_with_lines("#[gone.ligma]", &[], from(files));
+        assert_eq!(result.errors[0].kind, ErrorKind::Include);
+        assert_eq!(result.errors[0].message, "couldn't find `gone.ligma` to include (looked in .)");
+    }
+}
@@ -0,0 +1,275 @@
+use crate::ligma::include::IncludeOptions;
+use crate::ligma::lexer::{Lexer, Spanned, Token};
+use crate::ligma::run::parse_source;
+use crate::ligma::serendipity::{ErrorKind, LigmaError};
+
+const INDENT: &str = "    ";
+
+// Works on tokens rather than the AST so comments make it through. Includes
+// aren't followed, #[path] just gets copied across like any other statement.
+// Anything that doesn't lex and parse is handed back untouched as errors.
+pub fn format_source(source: &str, includes: &IncludeOptions) -> Result<String, Vec<LigmaError>> {
+    parse_source(source, includes)?;
+    let tokens = top_level_tokens(source, includes);
+    let mut formatter = Formatter::new(source);
+    for at in 0..tokens.len() {
+        formatter.token(&tokens, at);
+    }
+    let formatted = formatter.finish();
+    // a formatter that changes what the program means is worse than none at all
+    let before: Vec<Token> = tokens.into_iter().map(|spanned| spanned.token).collect();
+    let after: Vec<Token> = top_level_tokens(&formatted, includes).into_iter().map(|spanned| spanned.token).collect();
+    if before != after {
+        let msg = String::from("formatting would change how this program lexes, so it was left alone");
+        return Err(vec![LigmaError::new(ErrorKind::UnexpectedToken, msg)]);
+    }
+    return Ok(formatted);
+}
+
+// lex_all would splice includes in, fmt wants to see #[path] itself
+fn top_level_tokens(source: &str, includes: &IncludeOptions) -> Vec<Spanned> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_includes(includes);
+    let mut tokens: Vec<Spanned> = Vec::new();
+    loop {
+        let spanned = lexer.lex_next_token();
+        if spanned.token == Token::EOF {
+            break;
+        }
+        tokens.push(spanned);
+    }
+    return tokens;
+}
+
+fn index_prefix(index: &Option<String>) -> String {
+    match index {
+        Some(i) => format!("[{}]", i),
+        None => String::new(),
+    }
+}
+
+// how a token is written, literals and comments keep whatever the source had
+fn render(spanned: &Spanned, source: &str) -> String {
+    let slice = || source[spanned.span.start..spanned.span.end].to_string();
+    match &spanned.token {
+        Token::Include(_) | Token::Integer(_) | Token::Float(_) | Token::String(_) | Token::Comment(_) | Token::Illegal(..) => slice(),
+        Token::SysCall(name) => format!("#{}#", name),
+        Token::Def(name) => format!("#<{}>", name),
+        Token::Insert(name) => format!("#({})", name),
+        Token::Identifier(index, name) => format!("{}{}", index_prefix(index), name),
+        Token::Function(name) => format!("{}{{", name),
+        Token::Call(name) => format!("{}(", name),
+        Token::Bool(true) => String::from("#T#"),
+        Token::Bool(false) => String::from("#F#"),
+        Token::Add => String::from("+"),
+        Token::Sub => String::from("-"),
+        Token::Multiply => String::from("*"),
+        Token::Divide => String::from("/"),
+        Token::Mod => String::from("%"),
+        Token::Eq => String::from("=="),
+        Token::Neq => String::from("=!"),
+        Token::Lt => String::from("<"),
+        Token::Gt => String::from(">"),
+        Token::LtEq => String::from("<="),
+        Token::GtEq => String::from(">="),
+        Token::Or => String::from("|"),
+        Token::And => String::from("&"),
+        Token::Xor => String::from("^"),
+        Token::Not => String::from("~"),
+        Token::LParen => String::from("("),
+        Token::RParen => String::from(")"),
+        Token::LBrack => String::from("{"),
+        Token::RBrack => String::from("}"),
+        Token::LSquare => String::from("["),
+        Token::RSquare => String::from("]"),
+        Token::Colon => String::from(":"),
+        Token::Set => String::from("="),
+        Token::Semicolon => String::from(";"),
+        Token::Comma => String::from(","),
+        Token::Return => String::from("@@"),
+        // @ = value; and a plain @ in an expression are the same token
+        Token::Argreg(index) => {
+            if slice().contains('=') {
+                format!("{}@ =", index_prefix(index))
+            } else {
+                format!("{}@", index_prefix(index))
+            }
+        }
+        Token::Loop(index, name) => format!("{}${}{{", index_prefix(index), name),
+        Token::Each(name) => format!("${}:", name),
+        Token::Break => String::from("$;"),
+        Token::If(index, name) => format!("{}?{}{{", index_prefix(index), name),
+        Token::IfExp => String::from("?"),
+        Token::ElseIf(index, name) => format!("{}?:{}{{", index_prefix(index), name),
+        Token::ElseIfExp => String::from("?:"),
+        Token::Else => String::from("?{"),
+        Token::LoopExp => String::from("$"),
+        Token::Output(index, name) => format!("!{}{};", index_prefix(index), name),
+        Token::OutputIndexed(index, name) => format!("!{}{}", index_prefix(index), name),
+        Token::OutputStr => String::from("!"),
+        Token::Input(index, name) => format!("{}??{};", index_prefix(index), name),
+        Token::Assign(index, name) => format!("{}{} =", index_prefix(index), name),
+        Token::Kill(index, name) => format!("{}**{};", index_prefix(index), name),
+        Token::EOF => String::new(),
+    }
+}
+
+// things that end a value, so a - after one of them is subtraction
+fn ends_value(token: &Token) -> bool {
+    matches!(token, Token::Identifier(_,_) | Token::Integer(_) | Token::Float(_) | Token::String(_) |
+             Token::Bool(_) | Token::RParen | Token::RSquare | Token::RBrack | Token::Argreg(_))
+}
+
+// things that can only ever start a statement, so they always get their own line
+fn starts_stmt(token: &Token) -> bool {
+    matches!(token, Token::Assign(_,_) | Token::Output(_,_) | Token::OutputIndexed(_,_) | Token::OutputStr | Token::Input(_,_) |
+             Token::Kill(_,_) | Token::Function(_) | Token::If(_,_) | Token::IfExp | Token::Loop(_,_) |
+             Token::LoopExp | Token::Each(_) | Token::Def(_) | Token::Insert(_) | Token::Return |
+             Token::Break | Token::Include(_) | Token::SysCall(_))
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    indent: usize,
+    // true for a block, false for a {key: value} map
+    braces: Vec<bool>,
+    parens: usize,
+    newline: bool,
+    in_output_str: bool,
+    last_line: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Formatter<'a> {
+        Formatter {
+            source,
+            out: String::new(),
+            indent: 0,
+            braces: Vec::new(),
+            parens: 0,
+            newline: false,
+            in_output_str: false,
+            last_line: 1,
+        }
+    }
+
+    // newlines are only written once the next token shows up, so a comment
+    // that sat at the end of a line can stay there
+    fn start_token(&mut self, spanned: &Spanned, space: bool) {
+        let same_line = spanned.span.line == self.last_line;
+        if self.newline && matches!(spanned.token, Token::Comment(_)) && same_line && self.out.len() > 0 {
+            self.out.push(' ');
+            return;
+        }
+        if self.newline {
+            self.newline = false;
+            if self.out.len() > 0 {
+                self.out.push('\n');
+                // one blank line survives, more than that doesn't
+                if spanned.span.line > self.last_line + 1 {
+                    self.out.push('\n');
+                }
+            }
+            for _ in 0..self.indent {
+                self.out.push_str(INDENT);
+            }
+        } else if space && self.out.len() > 0 && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+    }
+
+    fn token(&mut self, tokens: &[Spanned], at: usize) {
+        let spanned = &tokens[at];
+        let prev = if at > 0 { Some(&tokens[at - 1].token) } else { None };
+        let next = tokens.get(at + 1).map(|next| &next.token);
+        let text = render(spanned, self.source);
+
+        if starts_stmt(&spanned.token) && self.parens == 0 && !self.out.is_empty() && !self.newline {
+            // a statement that followed one without a ;
+            if !matches!(spanned.token, Token::Else | Token::ElseIf(_,_) | Token::ElseIfExp) {
+                self.newline = true;
+            }
+        }
+        let block_close = matches!(spanned.token, Token::RBrack) && self.braces.last() == Some(&true);
+        if block_close {
+            self.indent = self.indent.saturating_sub(1);
+            self.newline = true;
+        }
+
+        let space = match (prev, &spanned.token) {
+            (None, _) => false,
+            (_, Token::RParen) | (_, Token::RSquare) | (_, Token::Comma) | (_, Token::Semicolon) | (_, Token::Colon) => false,
+            (Some(Token::LParen), _) | (Some(Token::LSquare), _) | (Some(Token::Call(_)), _) | (Some(Token::Not), _) => false,
+            (Some(Token::IfExp), _) | (Some(Token::LoopExp), _) | (Some(Token::ElseIfExp), _) | (Some(Token::Each(_)), _) => false,
+            (Some(Token::OutputStr), _) | (Some(Token::OutputIndexed(_,_)), _) => false,
+            // { after ) or #<name> opens a block and hugs it, a map keeps its distance
+            (Some(Token::RParen), Token::LBrack) | (Some(Token::Def(_)), Token::LBrack) => false,
+            (Some(Token::LBrack), _) if self.braces.last() == Some(&false) => false,
+            (_, Token::RBrack) if self.braces.last() == Some(&false) => false,
+            // unary - and + stick to what they're in front of
+            (Some(Token::Sub), _) | (Some(Token::Add), _) if at < 2 || !ends_value(&tokens[at - 2].token) => false,
+            // a [ straight after a value indexes it
+            (Some(prev), Token::LSquare) if ends_value(prev) => false,
+            _ => true,
+        };
+        self.start_token(spanned, space);
+        self.out.push_str(&text);
+        self.last_line = spanned.span.line + text.matches('\n').count();
+
+        match &spanned.token {
+            Token::LParen | Token::Call(_) => {
+                self.parens += 1;
+            }
+            Token::RParen => {
+                self.parens = self.parens.saturating_sub(1);
+            }
+            Token::LBrack => {
+                let block = matches!(prev, Some(Token::RParen) | Some(Token::Def(_)));
+                self.braces.push(block);
+                if block {
+                    self.indent += 1;
+                    self.newline = true;
+                }
+            }
+            Token::Function(_) | Token::If(_,_) | Token::ElseIf(_,_) | Token::Else | Token::Loop(_,_) => {
+                self.braces.push(true);
+                self.indent += 1;
+                self.newline = true;
+            }
+            Token::RBrack => {
+                self.braces.pop();
+                if block_close {
+                    // } ?{ and } ?:( keep an else chain together
+                    if !matches!(next, Some(Token::Else) | Some(Token::ElseIf(_,_)) | Some(Token::ElseIfExp)) {
+                        self.newline = true;
+                    }
+                }
+            }
+            Token::OutputStr => {
+                self.in_output_str = true;
+            }
+            Token::Semicolon => {
+                // !"a"; "b"; is still one statement
+                if self.in_output_str && matches!(next, Some(Token::String(_))) {
+                    self.out.push(' ');
+                } else {
+                    self.in_output_str = false;
+                    self.newline = true;
+                }
+            }
+            Token::Output(_,_) | Token::Input(_,_) | Token::Kill(_,_) | Token::Break | Token::Include(_) | Token::SysCall(_) | Token::Comment(_) => {
+                self.newline = true;
+            }
+            Token::Insert(_) if !matches!(next, Some(Token::Semicolon)) => {
+                self.newline = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push('\n');
+        return self.out;
+    }
+}
@@ -1,69 +1,99 @@
 use crate::Expr;
+use crate::ligma::serendipity::{ErrorKind, LigmaError, Span};
 
-use std::sync::{Arc, Mutex};
-use std::io;
+use std::sync::Arc;
 use crate::Token;
-use std::collections::HashMap;
-macro_rules! parse {
-    (|$self:ident.$peek:ident(), $token:ident| { $($body:tt)* }) => {
-        while let Some($token) = $self.$peek() {
-            match $token {
-                $($body)*
-            }
-        }
-    };
-}
+use crate::ligma::config::InterpreterConfig;
+use crate::ligma::input::{InputSource, StdinInput};
+use crate::ligma::output::{BufferSink, OutputSink};
+use crate::ligma::shared::Shared;
+use crate::ligma::stmt::{DefBody, Stmt};
+use std::collections::{BTreeMap, HashMap};
 //Stackoverflows shouldn't be possible now unless your computer sucks?
-//Any sub function or expression should only evaluate if under the limit
-const RECURS_LIMIT: i64 = 300;
+//Any sub function or expression should only evaluate if under the limits in InterpreterConfig
 
 
 // Don't ask me why I chose such a terrible data structure. I don't know and I don't want to
 // rewrite at this point. 
 //
-// ArcArg is a static return register
-// ArcFuns is a static register of all past declared functions
-// ArcDefs is a static register of all past defined macros
 // VarList is a private variable list only accessable to the current function
-type ArcArg = Arc<Mutex<HashMap<Option<String>, Expr>>>;
-type ArcFuns = Arc<Mutex<HashMap<String, Fun>>>;
-type ArcDefs = Arc<Mutex<HashMap<String, Vec<Token>>>>;
 type VarList = HashMap<String, HashMap<Option<String>, Expr>>;
-// I want to embed in wasm, so no stack overflow allowed :(
-type SoMonitor = Arc<Mutex<i64>>;
-type Printer = Arc<Mutex<String>>;
+
+// Everything the frames of one program share, all behind the one Shared.
+// It used to be an Arc<Mutex<_>> per field, see shared.rs for why it isn't.
+#[derive(Debug)]
+struct Context {
+    // the return register
+    argreg: HashMap<Option<String>, Expr>,
+    // every function declared so far
+    functions: HashMap<String, Declared>,
+    // every def defined so far
+    defs: HashMap<String, DefBody>,
+    // I want to embed in wasm, so no stack overflow allowed :(
+    stack: i64,
+    // Nor is hanging the tab forever. Every statement, loop turn and operator
+    // burns one step, a string repeat one more per copy, None means run as
+    // long as you like.
+    fuel: Option<u64>,
+    // !name; writes here, a buffer unless someone says otherwise
+    output: Box<dyn OutputSink>,
+    // how many bytes have gone to output so far, for max_output_bytes
+    written: usize,
+    // ??name; reads from here, stdin unless someone says otherwise
+    input: Box<dyn InputSource>,
+}
+
+// what name(a, b){ ... } leaves behind, every call makes a fresh frame out of it
+#[derive(Debug, Clone)]
+struct Declared {
+    // empty for the old name{ } functions, which only ever get _@
+    params: Vec<String>,
+    body: Arc<Vec<Stmt>>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Fun {
-    defs: ArcDefs,
-    argreg: ArcArg,
-    functions: ArcFuns,
-    tokens: Vec<Token>,
+    ctx: Shared<Context>,
+    body: Arc<Vec<Stmt>>,
     vars: VarList,    
-    position: usize,
-    loop_start: Vec<usize>,
-    paren_counter: Vec<i64>,
-    stack: SoMonitor,
-    output: Printer,
+    config: InterpreterConfig,
+    // how deep eval_exp is in this frame
+    nesting: usize,
+}
+
+// what the sync feature is for, this stops building the day something in Fun isn't Send
+#[cfg(feature = "sync")]
+const _: () = {
+    fn send<T: Send>() {}
+    let _ = send::<Fun>;
+};
+
+// how a block finished, so a `$;` can find its way out to the loop it breaks
+// and a `@@` can find its way out of the function
+#[derive(Debug)]
+enum Flow {
+    Next,
+    Break(Span),
+    Return(Option<Expr>),
 }
 
 
 // Perfect naming conventions
 impl Fun {
-    fn mod_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn mod_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -71,7 +101,7 @@ impl Fun {
             }
             Expr::Int(t) => {
                 let b = Expr::parse_exp_integer(b);
-                a = Expr::Int(t % b);
+                a = Expr::Int(Fun::int_divide(t, &Token::Mod, b)?);
             }
             Expr::Float(t) => {
                 let b = Expr::parse_exp_float(b);
@@ -89,22 +119,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn div_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn div_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -112,7 +142,7 @@ impl Fun {
             }
             Expr::Int(t) => {
                 let b = Expr::parse_exp_integer(b);
-                a = Expr::Int(t / b);
+                a = Expr::Int(Fun::int_divide(t, &Token::Divide, b)?);
             }
             Expr::Float(t) => {
                 let b = Expr::parse_exp_float(b);
@@ -133,23 +163,23 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
 
-    fn mult_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn mult_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -157,40 +187,40 @@ impl Fun {
             }
             Expr::Int(t) => {
                 let b = Expr::parse_exp_integer(b);
-                a = Expr::Int(t * b);
+                a = Expr::Int(Fun::int_arith(t, &Token::Multiply, b)?);
             }
             Expr::Float(t) => {
                 let b = Expr::parse_exp_float(b);
                 a = Expr::Float(t * b);
             }
             Expr::String(t) => {
-                let b = Expr::parse_exp_integer(b);
-                let mut tt = format!("{}",t);
-                for _c in 0..b{
-                    tt = format!("{}{}",tt,t);
+                let copies = Fun::copies(Expr::parse_exp_integer(b));
+                if (t.len() as u64).checked_mul(copies).and_then(|len| usize::try_from(len).ok()).is_none() {
+                    return Err(LigmaError::new(ErrorKind::LimitExceeded, String::from("that string is too long to make")));
                 }
-                a = Expr::String(tt);
+                // an empty string stays empty however many copies, even ones usize can't count
+                a = Expr::String(t.repeat(copies.min(usize::MAX as u64) as usize));
             }
             _ => {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn sub_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn sub_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -198,7 +228,7 @@ impl Fun {
             }
             Expr::Int(t) => {
                 let b = Expr::parse_exp_integer(b);
-                a = Expr::Int(t - b);
+                a = Expr::Int(Fun::int_arith(t, &Token::Sub, b)?);
             }
             Expr::Float(t) => {
                 let b = Expr::parse_exp_float(b);
@@ -216,23 +246,23 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
 
-    fn add_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn add_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -240,36 +270,38 @@ impl Fun {
             }
             Expr::Int(t) => {
                 let b = Expr::parse_exp_integer(b);
-                a = Expr::Int(t + b);
+                a = Expr::Int(Fun::int_arith(t, &Token::Add, b)?);
             }
             Expr::Float(t) => {
                 let b = Expr::parse_exp_float(b);
                 a = Expr::Float(t + b);
             }
             Expr::String(t) => {
-                let b = Expr::parse_exp_string(b);
-                a = Expr::String(format!("{}{}",b,t));
+                // yes, b goes first
+                let mut b = Expr::parse_exp_string(b);
+                b.push_str(&t);
+                a = Expr::String(b);
             }
             _ => {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn eq_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn eq_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -287,26 +319,29 @@ impl Fun {
                 let b = Expr::parse_exp_string(b);
                 a = Expr::Bool(t == b);
             }
+            Expr::List(_) | Expr::Map(_) => {
+                a = Expr::Bool(exp_a == b);
+            }
             _ => {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn neq_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn neq_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -324,26 +359,29 @@ impl Fun {
                 let b = Expr::parse_exp_string(b);
                 a = Expr::Bool(t != b);
             }
+            Expr::List(_) | Expr::Map(_) => {
+                a = Expr::Bool(exp_a != b);
+            }
             _ => {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn lt_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn lt_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -365,22 +403,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn gt_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn gt_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -402,22 +440,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn lteq_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn lteq_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -439,22 +477,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn gteq_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn gteq_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -476,22 +514,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn or_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn or_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -516,22 +554,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn and_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn and_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -556,22 +594,22 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn xor_exp(&mut self, exp_a: Expr, exp_b: Expr) -> Expr {
+    fn xor_exp(exp_a: Expr, exp_b: Expr) -> Result<Expr, LigmaError> {
         let a: Expr;
         let b: Expr;
         match exp_b {
-            Expr::BinOp(_,_,_) => {
-                b = self.eval_binop(exp_b);
+            Expr::BinOp(..) => {
+                b = Fun::eval_binop(exp_b)?;
             }
             _ => {
                 b = exp_b;
             }
         }
         match exp_a {
-            Expr::BinOp(_,_,_) => {
-                a = self.eval_binop(exp_a);
+            Expr::BinOp(..) => {
+                a = Fun::eval_binop(exp_a)?;
             }
             Expr::Bool(t) => {
                 let b = Expr::parse_exp_boolean(b);
@@ -596,1268 +634,943 @@ impl Fun {
                 a = exp_a;
             }
         }
-        return a;
+        return Ok(a);
     }
-    fn eval_binop(&mut self, exp: Expr) -> Expr {
-        let stack = self.get_stack();
-        {
-            let mut stack = stack.lock().unwrap();
-            *stack += 1;
-
-            if *stack < RECURS_LIMIT {
-                drop(stack);
-                match exp {
-                    Expr::BinOp(exp_a, op, exp_b) => {
-                        let exp_a = *exp_a;
-                        let exp_b = *exp_b;
-
-                        let mut new_exp = Expr::New;
-                        match op {
-                            Token::Add => {
-                                new_exp = self.add_exp(exp_a,exp_b);
-                            }
-                            Token::Sub => {
-                                new_exp = self.sub_exp(exp_a,exp_b);
-                            }
-                            Token::Multiply => {
-                                new_exp = self.mult_exp(exp_a,exp_b);
-                            }
-                            Token::Divide => {
-                                new_exp = self.div_exp(exp_a,exp_b);
-                            }
-                            Token::Mod => {
-                                new_exp = self.mod_exp(exp_a,exp_b);
-                            }
-                            Token::Eq => {
-                                new_exp = self.eq_exp(exp_a,exp_b);
-                            }
-                            Token::Neq => {
-                                new_exp = self.neq_exp(exp_a,exp_b);
-                            }
-                            Token::Lt => {
-                                new_exp = self.lt_exp(exp_a,exp_b);
-                            }
-                            Token::LtEq => {
-                                new_exp = self.lteq_exp(exp_a,exp_b);
-                            }
-                            Token::Gt => {
-                                new_exp = self.gt_exp(exp_a,exp_b);
-                            }
-                            Token::GtEq => {
-                                new_exp = self.gteq_exp(exp_a,exp_b);
-                            }
-                            Token::Or => {
-                                new_exp = self.or_exp(exp_a,exp_b);
-                            }
-                            Token::And => {
-                                new_exp = self.and_exp(exp_a,exp_b);
-                            }
-                            Token::Xor => {
-                                new_exp = self.xor_exp(exp_a,exp_b);
-                            }
-                            _ => {
-
-                            }
-                        }
-                        let stack = self.get_stack();
-                        {
-                            let mut stack = stack.lock().unwrap();
-                            *stack -= 1;
-                        }
-                        return new_exp;
-                    }
-                    _ => {
-                    }
-                }
+    fn eval_binop(exp: Expr) -> Result<Expr, LigmaError> {
+        match exp {
+            Expr::BinOp(exp_a, op, exp_b, span) => {
+                return Fun::binop(*exp_a, &op, *exp_b).map_err(|e| e.with_span(span));
+            }
+            _ => {
+                return Ok(exp);
             }
         }
-        let stack = self.get_stack();
-        {
-            let mut stack = stack.lock().unwrap();
-            *stack -= 1;
-        }
-        return exp;
-        
     }
-    fn eval_exp(&mut self, index: Option<String>, name: String) -> Expr {
-        let mut expr_a = Expr::New;
-        let mut op = Token::Illegal(0,"expected an op".to_string());
-        parse!(|self.peek(), token| {
-            Token::RParen => {
-                self.advance();
-                break;
-            }
 
-            Token::LParen => {
-                self.advance();
-                let new_exp = self.eval_exp(index.clone(), name.clone());
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
+    // "s" * n is the string and then n more of it, nothing below 0 takes any away
+    pub fn copies(n: i64) -> u64 {
+        return n.max(0) as u64 + 1;
+    }
 
-            }
-            Token::Semicolon => {
-                self.advance();
-                match expr_a {
-                    Expr::New => {
-                        expr_a = Expr::Bool(false);
-                    }
-                    _ => {}
-                }
-                if let Some(i) = self.vars.get_mut(&name) {
-                    i.insert(index, expr_a.clone());
-                } else {
-                    let mut new_exp: HashMap<Option<String>, Expr> = HashMap::new();
-                    new_exp.insert(index, expr_a.clone());
-                    self.vars.insert(name, new_exp);
-                }
-                break;
-            }
-            Token::Call(name, args) => {
-                self.advance();
-                self.call_func(name, args);
-                let mut new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                {
-                    let argreg = self.get_argreg();
-                    let mut argreg = argreg.lock().unwrap();
-                    // bet you didn't know you could
-                    // index None. I feel like this shouldn't
-                    // be allowed. I would love to know how the
-                    // hashing function handles this. This may
-                    // seem like a bad practice, but this
-                    // ensures that the user can only access
-                    // this register by not indexing in the actual language.
-                    let index: Option<String> = None;  
-                    
-                    if let Some(i) = argreg.get_mut(&index) {
-                        new_exp = i.clone();
-                    } else {
-                        //TODO: serendipity
-                    }
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
-            }
-            Token::Argreg(index) => {
-                self.advance();
-                let mut new_exp = Expr::Bool(false);
-                {
-                    let argreg = self.get_argreg();
-                    let mut argreg = argreg.lock().unwrap();
-                    let mut index_eval: Option<String> = None;
-                    //yes very readable. took two days to fiure out what was going on;
-                    if let Some(i) = index {
-                        if let Some(i) = self.vars.get(&i){
-                            if let Some(i) = i.get(&index_eval) {
-                                index_eval = Some(Expr::parse_exp_string(i.clone()));
-                            }
-                        }
-                    }
+    // Steps an operator costs on top of the one every operator burns. Only a
+    // string repeat does more work the bigger the number, so every copy past
+    // the first is a step of its own and a budget can't be blown on one line.
+    pub fn extra_steps(exp_a: &Expr, op: &Token, exp_b: &Expr) -> u64 {
+        if let (Expr::String(_), Token::Multiply) = (exp_a, op) {
+            return Fun::copies(Expr::parse_exp_integer(exp_b.clone())) - 1;
+        }
+        return 0;
+    }
 
-                    if let Some(i) = argreg.get_mut(&index_eval) {
-                        new_exp = i.clone();
-                    } else {
-                        //TODO: serendipity
-                    }
-                }
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
-            }
-            Token::Identifier(index, name) => {
-                self.advance();
-                let mut new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if let Some(i) = self.vars.get_mut(&name) {
-                    if let Some(i) = i.get(&index) {
-                        new_exp = i.clone();
-                    }
-                } else {
-                    //TODO: serendipity
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
-            }
-            Token::Integer(val) => {
-                self.advance();
-                let new_exp = Expr::Int(val);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
+    // How many bytes a string * or + is going to come out as, so one that's
+    // over max_string_length can be turned down before it's built. None for
+    // everything else, which either isn't a string or can't get much longer
+    // than what went in.
+    pub fn string_len(exp_a: &Expr, op: &Token, exp_b: &Expr) -> Option<u128> {
+        let t = match exp_a {
+            Expr::String(t) => t,
+            _ => {
+                return None;
             }
-
-            Token::Float(val) => {
-                self.advance();
-                let new_exp = Expr::Float(val);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
+        };
+        match (op, exp_b) {
+            (Token::Multiply, _) => {
+                let copies = Fun::copies(Expr::parse_exp_integer(exp_b.clone()));
+                return Some(t.len() as u128 * copies as u128);
             }
-
-            Token::Bool(val) => {
-                self.advance();
-                let new_exp = Expr::Bool(val);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
+            (Token::Add, Expr::String(b)) => {
+                return Some(t.len() as u128 + b.len() as u128);
             }
-
-            Token::String(val) => {
-                self.advance();
-                let new_exp = Expr::String(val);
-                //TODO: serendipity
-                let mut has_a = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if has_a {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                } else {
-                    expr_a = new_exp;
-                }
-                op = Token::Illegal(0,"expected an op".to_string());
+            _ => {
+                return None;
             }
+        }
+    }
 
-            Token::Multiply => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Multiply;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
-            }
-            Token::Divide => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Divide;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+    // one operator on two values that are already evaluated, eval_exp already
+    // counted this level against max_expr_nesting. The bytecode VM uses it too,
+    // so the two can't disagree about what "a" - 1 is.
+    pub fn binop(exp_a: Expr, op: &Token, exp_b: Expr) -> Result<Expr, LigmaError> {
+        if let (Expr::Int(t), Expr::Int(b)) = (&exp_a, &exp_b) {
+            if let Some(value) = Fun::int_binop(*t, op, *b) {
+                return value;
             }
+        }
+        let mut new_exp = Expr::New;
+        match op {
             Token::Add => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Add;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::add_exp(exp_a,exp_b)?;
             }
             Token::Sub => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Sub;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::sub_exp(exp_a,exp_b)?;
+            }
+            Token::Multiply => {
+                new_exp = Fun::mult_exp(exp_a,exp_b)?;
+            }
+            Token::Divide => {
+                new_exp = Fun::div_exp(exp_a,exp_b)?;
             }
-
             Token::Mod => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Mod;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::mod_exp(exp_a,exp_b)?;
             }
-
-
             Token::Eq => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Eq;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::eq_exp(exp_a,exp_b)?;
             }
             Token::Neq => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Neq;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::neq_exp(exp_a,exp_b)?;
             }
-
             Token::Lt => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Lt;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
-            }
-            Token::Gt => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Gt;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::lt_exp(exp_a,exp_b)?;
             }
             Token::LtEq => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::LtEq;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::lteq_exp(exp_a,exp_b)?;
+            }
+            Token::Gt => {
+                new_exp = Fun::gt_exp(exp_a,exp_b)?;
             }
             Token::GtEq => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::GtEq;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+                new_exp = Fun::gteq_exp(exp_a,exp_b)?;
+            }
+            Token::Or => {
+                new_exp = Fun::or_exp(exp_a,exp_b)?;
             }
             Token::And => {
-                self.advance();
+                new_exp = Fun::and_exp(exp_a,exp_b)?;
+            }
+            Token::Xor => {
+                new_exp = Fun::xor_exp(exp_a,exp_b)?;
+            }
+            _ => {
 
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::And;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
-                }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
             }
-            Token::Or => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Or;
-                        }
-                    }
+        }
+        return Ok(new_exp);
+    }
+    // two ints is most of what loops do, this is what the helpers above would
+    // have come up with anyway. None for the operators that don't bother with ints.
+    // An answer that doesn't fit in an i64 is an error, same as dividing by zero.
+    pub fn int_binop(t: i64, op: &Token, b: i64) -> Option<Result<Expr, LigmaError>> {
+        let value = match op {
+            Token::Add | Token::Sub | Token::Multiply => {
+                return Some(Fun::int_arith(t, op, b).map(Expr::Int));
+            }
+            Token::Divide | Token::Mod => {
+                return Some(Fun::int_divide(t, op, b).map(Expr::Int));
+            }
+            Token::Eq => Expr::Bool(t == b),
+            Token::Neq => Expr::Bool(t != b),
+            Token::Lt => Expr::Bool(t < b),
+            Token::LtEq => Expr::Bool(t <= b),
+            Token::Gt => Expr::Bool(t > b),
+            Token::GtEq => Expr::Bool(t >= b),
+            _ => {
+                return None;
+            }
+        };
+        return Some(Ok(value));
+    }
+
+    // + - and * on two ints, nothing wraps round to the other end
+    fn int_arith(t: i64, op: &Token, b: i64) -> Result<i64, LigmaError> {
+        let (value, sign) = match op {
+            Token::Add => (t.checked_add(b), "+"),
+            Token::Sub => (t.checked_sub(b), "-"),
+            _ => (t.checked_mul(b), "*"),
+        };
+        match value {
+            Some(value) => {
+                return Ok(value);
+            }
+            None => {
+                return Err(LigmaError::new(ErrorKind::Arithmetic, format!("`{}` {} `{}` doesn't fit in an integer", t, sign, b)));
+            }
+        }
+    }
+
+    // / and % on two ints, the operator's span gets put on by whoever called binop
+    fn int_divide(t: i64, op: &Token, b: i64) -> Result<i64, LigmaError> {
+        let value = match op {
+            Token::Mod => t.checked_rem(b),
+            _ => t.checked_div(b),
+        };
+        match value {
+            Some(value) => {
+                return Ok(value);
+            }
+            None if b == 0 => {
+                return Err(LigmaError::new(ErrorKind::Arithmetic, format!("can't divide `{}` by zero", t)));
+            }
+            None => {
+                return Err(LigmaError::new(ErrorKind::Arithmetic, format!("`{}` divided by `{}` doesn't fit in an integer", t, b)));
+            }
+        }
+    }
+
+    // - and + only really mean something for numbers, everything else gets
+    // squashed into an Int first. ~ goes through the usual boolean coercion.
+    // There's no span on a unary op, so an error gets the statement's.
+    pub fn eval_unop(op: &Token, exp: Expr) -> Result<Expr, LigmaError> {
+        match op {
+            Token::Sub => {
+                match exp {
+                    Expr::Float(t) => Ok(Expr::Float(-t)),
                     _ => {
-                        has_op = true;
+                        let t = Expr::parse_exp_integer(exp);
+                        match t.checked_neg() {
+                            Some(value) => Ok(Expr::Int(value)),
+                            None => Err(LigmaError::new(ErrorKind::Arithmetic, format!("`-({})` doesn't fit in an integer", t))),
+                        }
                     }
                 }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
             }
-            Token::Xor => {
-                self.advance();
-                let new_exp = Expr::Bool(false);
-                //TODO: serendipity
-                let mut has_a = false;
-                let mut has_op = false;
-                match expr_a {
-                    Expr::New => {}
-                    _ => {has_a = true;}
-                }
-                match op {
-                    Token::Illegal(_,_) => {
-                        if !has_a {
-                            expr_a = new_exp.clone();
-                        } else {
-                            op = Token::Xor;
-                        }
-                    }
-                    _ => {
-                        has_op = true;
-                    }
+            Token::Add => {
+                match exp {
+                    Expr::Float(t) => Ok(Expr::Float(t)),
+                    _ => Ok(Expr::Int(Expr::parse_exp_integer(exp))),
                 }
-                if has_a && has_op {
-                    expr_a = self.eval_binop(Expr::BinOp(Box::new(expr_a.clone()), op.clone(), Box::new(new_exp)));
-                    op = Token::Illegal(0,"expected an op".to_string());
-                } 
+            }
+            Token::Not => {
+                Ok(Expr::Bool(!Expr::parse_exp_boolean(exp)))
             }
             _ => {
-                match expr_a {
-                    Expr::New => {
-                        expr_a = Expr::Bool(false);
-                        //TODO: serendipity
-                    }
-                    _ => {}
-                }
-                if let Some(i) = self.vars.get_mut(&name) {
-                    i.insert(index, expr_a.clone());
-                } else {
-                    let mut new_exp: HashMap<Option<String>, Expr> = HashMap::new();
-                    new_exp.insert(index, expr_a.clone());
-                    self.vars.insert(name, new_exp);
-                }
-                break;
+                Ok(exp)
             }
-        });
-        return expr_a;
+        }
     }
 
-    fn skip_def(&mut self, def_name: String) -> () {
-        let mut paren = 1;
-        let mut tokens: Vec<Token> = Vec::new();
+    // keeps count of how deep we are and checks what comes out, the actual
+    // work is in eval_exp_inner
+    fn eval_exp(&mut self, exp: &Expr) -> Result<Expr, LigmaError> {
+        if self.nesting >= self.config.max_expr_nesting {
+            let msg = format!("expression nests deeper than {} levels", self.config.max_expr_nesting);
+            return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+        }
+        self.nesting += 1;
+        let result = self.eval_exp_inner(exp);
+        self.nesting -= 1;
+        let value = result?;
+        self.check_string(&value)?;
+        return Ok(value);
+    }
 
-        parse!(|self.peek(), token| {
-            Token::Function(_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::If(_,_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::Loop(_,_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::LBrack => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::RBrack => {
-                paren -= 1;
-                self.tokens.remove(self.position);
-                if paren == 0 {
-                    break;
-                } else {
-                    tokens.push(token);
-                }
-                
+    fn check_string(&self, value: &Expr) -> Result<(), LigmaError> {
+        if let Expr::String(t) = value {
+            return Fun::check_length(t.len() as u128, self.config.max_string_length);
+        }
+        return Ok(());
+    }
+
+    // what check_string says about a string of len bytes, for when it hasn't been built yet
+    pub fn check_length(len: u128, max: Option<usize>) -> Result<(), LigmaError> {
+        if let Some(max) = max {
+            if len > max as u128 {
+                let msg = format!("string of {} bytes is longer than the {} allowed", len, max);
+                return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            }
+        }
+        return Ok(());
+    }
+
+    fn eval_exp_inner(&mut self, exp: &Expr) -> Result<Expr, LigmaError> {
+        match exp {
+            Expr::Var(index, name, span) => {
+                let index = self.eval_index(index.as_deref())?;
+                return self.get_var(&index, name).map_err(|e| e.with_span(span.clone()));
+            }
+            Expr::Argreg(index, span) => {
+                let index = self.eval_index(index.as_deref())?;
+                return self.get_argreg_slot(&index).map_err(|e| e.with_span(span.clone()));
+            }
+            Expr::Call(name, args, span) => {
+                if let Some(value) = self.call_func(name, args, span)? {
+                    return Ok(value);
+                }
+                // nothing came back through @@, so fall back on @
+                // bet you didn't know you could
+                // index None. I feel like this shouldn't
+                // be allowed. I would love to know how the
+                // hashing function handles this. This may
+                // seem like a bad practice, but this
+                // ensures that the user can only access
+                // this register by not indexing in the actual language.
+                let index: Option<String> = None;
+                return self.get_argreg_slot(&index).map_err(|e| e.with_span(span.clone()));
+            }
+            Expr::UnOp(op, exp) => {
+                let exp = self.eval_exp(exp)?;
+                return Fun::eval_unop(op, exp);
+            }
+            Expr::List(items) => {
+                let mut values: Vec<Expr> = Vec::new();
+                for item in items {
+                    values.push(self.eval_exp(item)?);
+                }
+                return Ok(Expr::List(values));
+            }
+            Expr::MapLit(entries) => {
+                let mut values: BTreeMap<String, Expr> = BTreeMap::new();
+                for (key, value) in entries {
+                    let key = Expr::parse_exp_string(self.eval_exp(key)?);
+                    values.insert(key, self.eval_exp(value)?);
+                }
+                return Ok(Expr::Map(values));
+            }
+            Expr::Index(target, key, span) => {
+                // a plain name gets indexed where it lives, copying a whole list
+                // out just to read one thing from it made every a[i] cost len(a)
+                if let (Expr::Var(None, name, var_span), true) = (&**target, self.nesting < self.config.max_expr_nesting) {
+                    self.var_ref(&None, name).map_err(|e| e.with_span(var_span.clone()))?;
+                    let key = self.eval_exp(key)?;
+                    let target = self.var_ref(&None, name).map_err(|e| e.with_span(var_span.clone()))?;
+                    return Fun::get_index(target, key).map_err(|e| e.with_span(span.clone()));
+                }
+                let target = self.eval_exp(target)?;
+                let key = self.eval_exp(key)?;
+                return Fun::get_index(&target, key).map_err(|e| e.with_span(span.clone()));
+            }
+            Expr::BinOp(exp_a, op, exp_b, span) => {
+                self.burn()?;
+                let exp_a = self.eval_exp(exp_a)?;
+                let exp_b = self.eval_exp(exp_b)?;
+                if let Some(len) = Fun::string_len(&exp_a, op, &exp_b) {
+                    Fun::check_length(len, self.config.max_string_length).map_err(|e| e.with_span(span.clone()))?;
+                }
+                let steps = Fun::extra_steps(&exp_a, op, &exp_b);
+                if steps > 0 {
+                    self.burn_steps(steps)?;
+                }
+                return Fun::binop(exp_a, op, exp_b).map_err(|e| e.with_span(span.clone()));
             }
             _ => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
+                return Ok(exp.clone());
             }
-        });
+        }
+    }
 
-        let defs = self.get_defs();
-        {
-            let mut defs = defs.lock().unwrap();
-            defs.insert(def_name, tokens.iter().rev().cloned().collect());
+    fn eval_index(&mut self, index: Option<&Expr>) -> Result<Option<String>, LigmaError> {
+        match index {
+            Some(index) => {
+                let index = self.eval_exp(index)?;
+                return Ok(Some(Expr::parse_exp_string(index)));
+            }
+            None => {
+                return Ok(None);
+            }
         }
     }
 
-    fn skip_fun(&mut self) -> Fun {
-        let mut paren = 1;
-        let mut tokens: Vec<Token> = Vec::new();
+    pub fn get_var(&self, index: &Option<String>, name: &str) -> Result<Expr, LigmaError> {
+        return self.var_ref(index, name).cloned();
+    }
 
-        parse!(|self.peek(), token| {
-            Token::Function(_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::If(_,_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::Loop(_,_) => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::LBrack => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
-                paren += 1;
-            }
-            Token::RBrack => {
-                paren -= 1;
-                self.tokens.remove(self.position);
-                if paren == 0 {
-                    break;
-                } else {
-                    tokens.push(token);
-                }
-                
+    fn var_ref(&self, index: &Option<String>, name: &str) -> Result<&Expr, LigmaError> {
+        if let Some(var) = self.vars.get(name) {
+            if let Some(var) = var.get(index) {
+                return Ok(var);
+            }
+        }
+        return Err(Fun::undefined(index, name));
+    }
+
+    fn var_mut(&mut self, index: &Option<String>, name: &str) -> Result<&mut Expr, LigmaError> {
+        match self.vars.get_mut(name).and_then(|var| var.get_mut(index)) {
+            Some(var) => {
+                return Ok(var);
+            }
+            None => {
+                return Err(Fun::undefined(index, name));
+            }
+        }
+    }
+
+    // the list itself for push and pop to change, not a copy of it
+    fn list_mut(&mut self, index: &Option<String>, name: &str, fun_name: &str) -> Result<&mut Vec<Expr>, LigmaError> {
+        match self.var_mut(index, name)? {
+            Expr::List(items) => {
+                return Ok(items);
             }
             _ => {
-                self.tokens.remove(self.position);
-                tokens.push(token);
+                let msg = format!("`{}` needs a list, `{}` isn't one", fun_name, name);
+                return Err(LigmaError::new(ErrorKind::TypeMismatch, msg));
             }
-        });
+        }
+    }
 
-        let fun = Fun::new_sub(tokens,self.get_defs(), 
-                               self.get_argreg(), 
-                               self.get_funcs(), 
-                               self.get_stack(), 
-                               self.get_output());
-        return fun;
+    fn undefined(index: &Option<String>, name: &str) -> LigmaError {
+        match index {
+            Some(i) => {
+                return LigmaError::undefined_variable(&format!("[{}]{}", i, name));
+            }
+            None => {
+                return LigmaError::undefined_variable(name);
+            }
+        }
     }
 
-    //TODO: Add errors
-    fn output_string(&mut self) -> () {
-        parse!(|self.peek(), token| {
-            Token::String(to_print) => {
-                self.advance();
-                
-                let printer = self.get_output();
-                {
-                    let mut printer = printer.lock().unwrap();
-                    printer.push_str(&to_print);
+    pub fn set_var(&mut self, index: Option<String>, name: &str, exp: Expr) -> Result<(), LigmaError> {
+        if let Some(i) = self.vars.get_mut(name) {
+            i.insert(index, exp);
+        } else {
+            if let Some(max) = self.config.max_variables {
+                if self.vars.len() >= max {
+                    let msg = format!("`{}` would be one variable more than the {} allowed", name, max);
+                    return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
                 }
+            }
+            let mut new_exp: HashMap<Option<String>, Expr> = HashMap::new();
+            new_exp.insert(index, exp);
+            self.vars.insert(name.to_string(), new_exp);
+        }
+        return Ok(());
+    }
 
-                parse!(|self.peek(), token| {
-                    Token::Semicolon => {
-                        self.advance();
-                    }
-                    _ => {
-                        //TODO: serendipity
-                        break;
-                    }
-                });
+    pub fn length(value: &Expr) -> Result<Expr, LigmaError> {
+        match value {
+            Expr::List(items) => {
+                return Ok(Expr::Int(items.len() as i64));
+            }
+            Expr::Map(entries) => {
+                return Ok(Expr::Int(entries.len() as i64));
+            }
+            Expr::String(t) => {
+                // in chars, the same as s[i] and $c:(s) count
+                return Ok(Expr::Int(t.chars().count() as i64));
             }
             _ => {
-                //TODO: serendipity
-                break;
+                return Err(LigmaError::new(ErrorKind::TypeMismatch, String::from("only lists, maps and strings have a length")));
             }
-        });
+        }
+    }
 
+    // list positions have to be in range, map keys have to exist, strings hand out chars
+    pub fn get_index(target: &Expr, key: Expr) -> Result<Expr, LigmaError> {
+        match target {
+            Expr::List(items) => {
+                let at = Fun::list_position(&key, items.len())?;
+                return Ok(items[at].clone());
+            }
+            Expr::Map(entries) => {
+                let key = Expr::parse_exp_string(key);
+                if let Some(value) = entries.get(&key) {
+                    return Ok(value.clone());
+                }
+                return Err(LigmaError::new(ErrorKind::BadIndex, format!("no key {:?} in this map", key)));
+            }
+            Expr::String(t) => {
+                let chars: Vec<char> = t.chars().collect();
+                let at = Fun::list_position(&key, chars.len())?;
+                return Ok(Expr::String(chars[at].to_string()));
+            }
+            _ => {
+                return Err(LigmaError::new(ErrorKind::BadIndex, String::from("only lists, maps and strings can be indexed")));
+            }
+        }
+    }
 
+    // writes value at the end of the keys, inside target where it already is
+    pub fn set_index(target: &mut Expr, keys: &[Expr], value: Expr) -> Result<(), LigmaError> {
+        let (key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => {
+                *target = value;
+                return Ok(());
+            }
+        };
+        match target {
+            Expr::List(items) => {
+                let at = Fun::list_position(key, items.len())?;
+                return Fun::set_index(&mut items[at], rest, value);
+            }
+            Expr::Map(entries) => {
+                let key = Expr::parse_exp_string(key.clone());
+                if let Some(inner) = entries.get_mut(&key) {
+                    return Fun::set_index(inner, rest, value);
+                }
+                // a missing key is fine as long as it's the last one
+                if rest.len() > 0 {
+                    return Err(LigmaError::new(ErrorKind::BadIndex, format!("no key {:?} in this map", key)));
+                }
+                entries.insert(key, value);
+                return Ok(());
+            }
+            _ => {
+                return Err(LigmaError::new(ErrorKind::BadIndex, String::from("only lists and maps can be assigned into")));
+            }
+        }
     }
 
-    fn peek(&mut self) -> Option<Token> {
-        if self.position < self.tokens.len() {
-            let token = self.tokens.get(self.position);
-            let token = token.unwrap().clone(); // shouldn't be possible to have none.
-            Some(token)
-        } else {
-            None
+    fn list_position(key: &Expr, len: usize) -> Result<usize, LigmaError> {
+        let at = match key {
+            Expr::Int(at) => *at,
+            _ => {
+                let msg = format!("a list index has to be an integer, not `{}`", Expr::parse_exp_string(key.clone()));
+                return Err(LigmaError::new(ErrorKind::BadIndex, msg));
+            }
+        };
+        if at < 0 || at as usize >= len {
+            return Err(LigmaError::new(ErrorKind::BadIndex, format!("index {} is out of range for length {}", at, len)));
         }
+        return Ok(at as usize);
     }
-    
-    fn advance(&mut self) {
-        self.position += 1;
+
+    fn get_argreg_slot(&self, index: &Option<String>) -> Result<Expr, LigmaError> {
+        let ctx = self.ctx.get();
+        if let Some(i) = ctx.argreg.get(index) {
+            return Ok(i.clone());
+        }
+        return Err(LigmaError::undefined_argreg(index));
     }
 
-    fn get_defs(&self) -> ArcDefs {
-        return Arc::clone(&self.defs);
+    fn print(&self, to_print: &str) -> Result<(), LigmaError> {
+        let mut ctx = self.ctx.get();
+        if let Some(max) = self.config.max_output_bytes {
+            if ctx.written + to_print.len() > max {
+                let msg = format!("output would go past the {} bytes allowed", max);
+                return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            }
+            ctx.written += to_print.len();
+        }
+        ctx.output.write(to_print);
+        return Ok(());
     }
 
-    fn get_argreg(&self) -> ArcArg {
-        return Arc::clone(&self.argreg);
+    // refills the tank for every frame that shares it, None takes the limit off
+    pub fn set_fuel(&mut self, steps: Option<u64>) {
+        self.ctx.get().fuel = steps;
     }
 
-    fn get_funcs(&self) -> ArcFuns {
-        return Arc::clone(&self.functions);
-    
+    fn burn(&self) -> Result<(), LigmaError> {
+        return self.burn_steps(1);
     }
 
-    fn get_stack(&self) -> SoMonitor {
-        return Arc::clone(&self.stack);
+    // running out on the last of several steps is the same as running out on the first
+    fn burn_steps(&self, steps: u64) -> Result<(), LigmaError> {
+        let mut ctx = self.ctx.get();
+        match ctx.fuel {
+            Some(left) if left < steps => {
+                return Err(LigmaError::new(ErrorKind::OutOfFuel, String::from("out of fuel, the program ran longer than its step budget")));
+            }
+            Some(left) => {
+                ctx.fuel = Some(left - steps);
+            }
+            None => {}
+        }
+        return Ok(());
     }
 
-    pub fn get_output(&self) -> Printer {
-        return Arc::clone(&self.output);
+    // whatever the sink held on to, see OutputSink::contents
+    pub fn get_output(&self) -> String {
+        return self.ctx.get().output.contents();
     }
 
-    fn call_func(&mut self, fun_name: String, arg_name: String) -> () {
-        let mut func_test: Fun = Fun::new(Vec::new());
-        let funcs = self.get_funcs();
-        {
-            let mut funcs = funcs.lock().unwrap();
-            if let Some(func) = funcs.get_mut(&fun_name) {
-                func_test = func.clone();
-                let inner_map = self.vars.get(&arg_name);  // Replace "some_key" with the actual key
-                if let Some(inner_map) = inner_map {
-                    let cloned_inner_map = inner_map.clone();
-                    func_test.vars.insert(String::from("_@"), cloned_inner_map);
-                }else{
-                    //TODO: serendipity
-                }
-            }
-        } let stack = self.get_stack();
-        {
-            let mut stack = stack.lock().unwrap();
-            *stack += 1;
+    // same deal as set_input
+    pub fn set_output(&mut self, sink: Box<dyn OutputSink>) {
+        self.ctx.get().output = sink;
+    }
 
-            if *stack < RECURS_LIMIT {
-                drop(stack);
-                //println!("{:?}", func_test.tokens);
-                func_test.eval();
-            } else {
-                //TODO: serendipity
-            }
+    // swaps the source in place, so functions that were already declared see it too
+    pub fn set_input(&mut self, source: Box<dyn InputSource>) {
+        self.ctx.get().input = source;
+    }
+
+    // a bare name hands over its whole [index] map like _@ always did,
+    // anything else gets evaluated here and shows up unindexed
+    fn eval_arg(&mut self, arg: &Expr) -> Result<HashMap<Option<String>, Expr>, LigmaError> {
+        if let Expr::Var(None, name, span) = arg {
+            if let Some(inner_map) = self.vars.get(name) {
+                return Ok(inner_map.clone());
+            }
+            return Err(LigmaError::undefined_variable(name).with_span(span.clone()));
         }
+        let mut inner_map: HashMap<Option<String>, Expr> = HashMap::new();
+        inner_map.insert(None, self.eval_exp(arg)?);
+        return Ok(inner_map);
     }
-    fn skip_loop(&mut self, mut paren: i64) -> () {
 
-        //println!("skipped {}",paren);
-        parse!(|self.peek(), token| {
-            Token::Function(_) => {
-                self.advance();
-                paren += 1;
-            }
-            Token::If(_,_) => {
-                self.advance();
-                paren += 1;
-            }
-            Token::Loop(_,_) => {
-                self.advance();
-                paren += 1;
-            }
-            Token::LBrack => {
-                self.advance();
-                paren += 1;
-            }
-            Token::RBrack => {
-                self.advance();
-                paren -= 1;
-                if paren == 0 {
-                    break;
-                }                
+    // len(a), push(list, value) and pop(list). Only looked at when there is no
+    // function by that name, so old scripts that wrote their own keep working.
+    fn call_builtin(&mut self, fun_name: &str, args: &[Expr]) -> Result<Option<Expr>, LigmaError> {
+        let arity = match fun_name {
+            "len" | "pop" => 1,
+            "push" => 2,
+            _ => {
+                return Err(LigmaError::undefined_function(fun_name));
             }
+        };
+        if args.len() != arity {
+            let msg = format!("`{}` takes {} argument(s) but was given {}", fun_name, arity, args.len());
+            return Err(LigmaError::new(ErrorKind::ArgumentCount, msg));
+        }
+        if fun_name == "len" {
+            // same as a[i], no copying a list out just to count it
+            if let (Expr::Var(None, name, span), true) = (&args[0], self.nesting < self.config.max_expr_nesting) {
+                let value = self.var_ref(&None, name).map_err(|e| e.with_span(span.clone()))?;
+                return Fun::length(value).map(Some);
+            }
+            let value = self.eval_exp(&args[0])?;
+            return Fun::length(&value).map(Some);
+        }
+        // push and pop change the list where it lives, so they need to know where that is
+        let (index, name) = match &args[0] {
+            Expr::Var(index, name, _) => (self.eval_index(index.as_deref())?, name.clone()),
             _ => {
-                self.advance();
+                let msg = format!("`{}` needs a variable holding a list", fun_name);
+                return Err(LigmaError::new(ErrorKind::TypeMismatch, msg));
+            }
+        };
+        self.list_mut(&index, &name, fun_name)?;
+        if fun_name == "push" {
+            let value = self.eval_exp(&args[1])?;
+            let items = self.list_mut(&index, &name, fun_name)?;
+            items.push(value);
+            return Ok(Some(Expr::Int(items.len() as i64)));
+        }
+        match self.list_mut(&index, &name, fun_name)?.pop() {
+            Some(value) => {
+                return Ok(Some(value));
             }
-        });
-
+            None => {
+                return Err(LigmaError::new(ErrorKind::BadIndex, format!("can't pop from empty list `{}`", name)));
+            }
+        }
+    }
 
+    fn call_func(&mut self, fun_name: &str, args: &[Expr], span: &Span) -> Result<Option<Expr>, LigmaError> {
+        let declared = self.ctx.get().functions.get(fun_name).cloned();
+        let declared = match declared {
+            Some(declared) => declared,
+            None => {
+                return self.call_builtin(fun_name, args);
+            }
+        };
+        // f() is a perfectly fine way to call something that doesn't care about _@
+        let mut names: Vec<String> = declared.params.clone();
+        if names.len() == 0 && args.len() == 1 {
+            names.push(String::from("_@"));
+        }
+        if names.len() != args.len() {
+            let msg = format!("`{}` takes {} argument(s) but was given {}", fun_name, names.len().max(1), args.len());
+            return Err(LigmaError::new(ErrorKind::ArgumentCount, msg));
+        }
+        // a function shares everything with whoever declared it except its vars
+        let mut func_test = Fun {
+            ctx: self.ctx.clone(),
+            body: declared.body,
+            vars: HashMap::new(),
+            config: self.config.clone(),
+            nesting: 0,
+        };
+        for (name, arg) in names.iter().zip(args) {
+            let inner_map = self.eval_arg(arg)?;
+            func_test.vars.insert(name.clone(), inner_map);
+        }
+        {
+            let mut ctx = self.ctx.get();
+            ctx.stack += 1;
+            if (ctx.stack as usize) >= self.config.max_call_depth {
+                ctx.stack -= 1;
+                let msg = format!("calling `{}` would go deeper than {} frames", fun_name, self.config.max_call_depth);
+                return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            }
+        }
+        match func_test.run() {
+            Ok(value) => {
+                return Ok(value);
+            }
+            Err(e) => {
+                let frame = match span.file {
+                    Some(_) => format!("function `{}` called at {}", fun_name, span),
+                    None => format!("function `{}` called at line {}", fun_name, span),
+                };
+                return Err(e.push_frame(frame));
+            }
+        }
     }
 
-    pub fn eval(&mut self) -> () {
-        parse!(|self.peek(), token| {
-            Token::If(index, name) => {
-                if let Some(mut count) = self.paren_counter.pop() {
-                    count += 1;
-                    self.paren_counter.push(count);
-                }else{
-                    //println!("if{:?}",self.paren_counter);
-                    self.paren_counter.push(1);
+    fn eval_block(&mut self, stmts: &[Stmt]) -> Result<Flow, LigmaError> {
+        for stmt in stmts {
+            let flow = self.eval_stmt(stmt).map_err(|e| e.with_span(stmt.span().clone()))?;
+            match flow {
+                Flow::Next => {}
+                _ => {
+                    return Ok(flow);
                 }
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
-                        }
+            }
+        }
+        return Ok(Flow::Next);
+    }
+
+    fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Flow, LigmaError> {
+        self.burn()?;
+        match stmt {
+            Stmt::Assign(index, name, exp, _) => {
+                let index_eval = self.eval_index(index.as_ref())?;
+                let exp = self.eval_exp(exp)?;
+                self.set_var(index_eval, name, exp)?;
+            }
+            Stmt::SetIndex(index, name, keys, exp, _) => {
+                let index_eval = self.eval_index(index.as_ref())?;
+                // only checked for now, the value goes in where the list or
+                // map lives so filling one up doesn't copy it every time
+                self.var_ref(&index_eval, name)?;
+                let mut key_evals: Vec<Expr> = Vec::new();
+                for key in keys {
+                    key_evals.push(self.eval_exp(key)?);
+                }
+                let exp = self.eval_exp(exp)?;
+                Fun::set_index(self.var_mut(&index_eval, name)?, &key_evals, exp)?;
+            }
+            Stmt::Argreg(index, exp, _) => {
+                let index_eval = self.eval_index(index.as_ref())?;
+                let exp = self.eval_exp(exp)?;
+                self.ctx.get().argreg.insert(index_eval, exp);
+            }
+            Stmt::If(cond, body, otherwise, _) => {
+                let check_var = self.eval_exp(cond)?;
+                if Expr::parse_exp_boolean(check_var) {
+                    return self.eval_block(body);
+                } else if let Some(otherwise) = otherwise {
+                    return self.eval_block(otherwise);
+                }
+            }
+            Stmt::Loop(cond, body, _) => {
+                loop {
+                    // $a{ } has no statements to burn anything, so the turn itself has to
+                    self.burn()?;
+                    let check_var = self.eval_exp(cond)?;
+                    if !Expr::parse_exp_boolean(check_var) {
+                        break;
                     }
-                }
-                let mut check_var: Option<Expr> = None;
-                if let Some(var) = self.vars.get(&name) {
-                        if let Some(var) = var.get(&index_eval) {
-                            check_var = Some(var.clone());
+                    match self.eval_block(body)? {
+                        Flow::Next => {}
+                        Flow::Break(_) => {
+                            break;
+                        }
+                        flow => {
+                            return Ok(flow);
                         }
-                }
-                if let Some(check_var) = check_var{
-                    //println!("if{:?}",self.paren_counter);
-                    if Expr::parse_exp_boolean(check_var) {
-                        self.advance();
-                        //println!("begin_if{:?}",self.paren_counter);
-                    } else {
-                        self.skip_loop(0);
-                        if let Some(mut count) = self.paren_counter.pop() {
-                            count -= 1;
-                            self.paren_counter.push(count);
-                        }       
                     }
-                } else {
-                    //TODO: serendipity
                 }
             }
-            Token::Loop(index, name) => {
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
+            Stmt::Each(name, collection, body, _) => {
+                // maps hand out their keys, strings their chars
+                let items: Vec<Expr> = match self.eval_exp(collection)? {
+                    Expr::List(items) => items,
+                    Expr::Map(entries) => entries.into_keys().map(Expr::String).collect(),
+                    Expr::String(t) => t.chars().map(|ch| Expr::String(ch.to_string())).collect(),
+                    _ => {
+                        return Err(LigmaError::new(ErrorKind::TypeMismatch, String::from("only lists, maps and strings can be looped over")));
+                    }
+                };
+                for item in items {
+                    self.burn()?;
+                    self.set_var(None, name, item)?;
+                    match self.eval_block(body)? {
+                        Flow::Next => {}
+                        Flow::Break(_) => {
+                            break;
+                        }
+                        flow => {
+                            return Ok(flow);
                         }
                     }
                 }
-                let mut check_var: Option<Expr> = None;
-                if let Some(var) = self.vars.get(&name) {
-                    if let Some(var) = var.get(&index_eval) {
-                        check_var = Some(var.clone());
+            }
+            Stmt::Break(span) => {
+                return Ok(Flow::Break(span.clone()));
+            }
+            Stmt::Output(exp, _) => {
+                let mut to_print = String::new();
+                match self.eval_exp(exp)? {
+                    Expr::Bool(t) => {
+                        to_print.push_str(&t.to_string());
                     }
-                }
-                if let Some(check_var) = check_var{
-                    if Expr::parse_exp_boolean(check_var) {
-                        self.loop_start.push(self.position);
-                        self.advance();
-                        self.paren_counter.push(1);
-                    } else {
-                        self.skip_loop(0);
+                    Expr::Int(t) => {
+                        to_print.push_str(&t.to_string());
+                    }
+                    Expr::Float(t) => {
+                        to_print.push_str(&t.to_string());
+                    }
+                    Expr::String(t) => {
+                        to_print.push_str(&t.to_string());
+                    }
+                    exp @ (Expr::List(_) | Expr::Map(_)) => {
+                        to_print.push_str(&Expr::parse_exp_string(exp));
+                    }
+                    _ => {
+                        return Err(LigmaError::unexpected("that holds nothing printable"));
                     }
-                //println!("loop{:?}",self.paren_counter);
-                } else {
-                    //TODO: serendipity
                 }
+                self.print(&to_print)?;
             }
-            Token::Break => {
-                self.advance();
-                if let Some(position) = self.loop_start.pop(){
-                    self.position = position;
+            Stmt::OutputStr(strings, _) => {
+                for to_print in strings {
+                    self.print(to_print)?;
                 }
-                self.skip_loop(0);
-            }
-            Token::RBrack => {
-                        //println!("\nright_brack_peek{:?} at {}",self.peek(), position);
-                self.advance();
-                        //println!("\nright_brack_peek{:?} at {}",self.peek(), position);
-                if let Some(mut count) = self.paren_counter.pop() {
-                    count -= 1;
-                    if count.clone() == 0 {
-                        if let Some(position) = self.loop_start.pop(){
-                            self.position = position;
-                        }
-                    } else {
-                        //
-                        //println!("rb{:?}",count);
-                        self.paren_counter.push(count);
-                    }
-                }else{
-                    //if let Some(position) = self.loop_start.pop(){
-                        //self.position = position;
-                    //}
-                }
-
             }
-            Token::Argreg(index) => {
-                self.advance();
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
-                        }
+            Stmt::Input(index, name, _) => {
+                let line = self.ctx.get().input.read_line();
+                let input: String;
+                match line {
+                    Some(line) => {
+                        input = line;
+                    }
+                    None => {
+                        let msg = format!("ran out of input to read into `{}`", name);
+                        return Err(LigmaError::new(ErrorKind::NoInput, msg));
                     }
                 }
-
-                let exp = self.eval_exp(index_eval.clone(), String::from("@"));
-                let argreg = self.get_argreg();
-                {
-                    let mut argreg = argreg.lock().unwrap();
-                    argreg.insert(index_eval, exp);
-                }
-
+                let input = Expr::String(input);
+                self.check_string(&input)?;
+                let index_eval = self.eval_index(index.as_ref())?;
+                self.set_var(index_eval, name, input)?;
             }
-
-            Token::Kill(_, name) => {
-                self.advance();
-                self.vars.remove(&name);
+            Stmt::Kill(name, _) => {
+                self.vars.remove(name);
                 self.vars.shrink_to_fit();
             }
-            Token::Assign(index, name) => {
-                self.advance();
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
-                        }
+            Stmt::Function(name, params, body, _) => {
+                let declared = Declared {
+                    params: params.clone(),
+                    body: Arc::new(body.clone()),
+                };
+                self.ctx.get().functions.insert(name.clone(), declared);
+            }
+            Stmt::Call(fun_name, args, span) => {
+                self.call_func(fun_name, args, span)?;
+            }
+            Stmt::Return(exp, _) => {
+                let value = match exp {
+                    Some(exp) => Some(self.eval_exp(exp)?),
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Def(def_name, body, _) => {
+                self.ctx.get().defs.insert(def_name.clone(), Arc::clone(body));
+            }
+            Stmt::Insert(def_name, _) => {
+                let body: DefBody;
+                match self.ctx.get().defs.get(def_name) {
+                    Some(def) => {
+                        body = Arc::clone(def);
                     }
-                }
-                self.eval_exp(index_eval, name);
-            }
-            Token::Output(index, name) => {
-                let mut to_print = String::new();
-                self.advance();
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
-                        }
+                    None => {
+                        return Err(LigmaError::new(ErrorKind::UndefinedDef, format!("undefined def `{}`", def_name)));
                     }
                 }
-                if let Some(vars) = self.vars.get(&name) {
-                    if let Some(output) = vars.get(&index_eval) {
-                        match output {
-                            Expr::Bool(t) => {
-                                to_print.push_str(&t.to_string());
-                            }
-                            Expr::Int(t) => {
-                                to_print.push_str(&t.to_string());
-                            }
-                            Expr::Float(t) => {
-                                to_print.push_str(&t.to_string());
-                            }
-                            Expr::String(t) => {
-                                to_print.push_str(&t.to_string());
-                            }
-                            _ => {
-                                //TODO: serendipity
-                            }
-                        }
-                    } else {
-                        //TODO: serendipity
+                // the parser already did the work, a broken def only
+                // complains now that somebody actually wants it
+                match &*body {
+                    Ok(stmts) => {
+                        return self.eval_block(stmts);
                     }
-                } else {
-                    //TODO: serendipity
-                }
-                let printer = self.get_output();
-                {
-                    let mut printer = printer.lock().unwrap();
-                    printer.push_str(&to_print);
-                }
-                
-            }
-            Token::OutputStr => {
-                self.advance();
-                self.output_string();
-            }
-            Token::Input(index, name) => {
-                self.advance();
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .expect("Failed to read line");
-
-                let input = input.trim().to_string();
-                //TODO: serendipity
-                let mut index_eval: Option<String> = None;
-                //yes very readable. took two days to fiure out what was going on;
-                if let Some(i) = index {
-                    if let Some(i) = self.vars.get(&i){
-                        if let Some(i) = i.get(&index_eval) {
-                            index_eval = Some(Expr::parse_exp_string(i.clone()));
-                        }
+                    Err(e) => {
+                        return Err(e.clone());
                     }
                 }
-                if let Some(i) = self.vars.get_mut(&name) {
-                    i.insert(index_eval, Expr::String(input));
-                } else {
-                    let mut new_exp: HashMap<Option<String>, Expr> = HashMap::new();
-                    new_exp.insert(index_eval, Expr::String(input));
-                    self.vars.insert(name, new_exp);
-                }
             }
-            Token::Call(fun_name, arg_name) => {
-                self.advance();
-                self.call_func(fun_name, arg_name);
-            }
-            Token::Def(def_name) => {
-                self.tokens.remove(self.position);
-                parse!(|self.peek(), token| {
-                    Token::LBrack => {
-                        self.tokens.remove(self.position);
-                        self.skip_def(def_name);
-                        break;
-                    }
-                    _ => {break}
+        }
+        return Ok(Flow::Next);
+    }
 
-                });
-            }
-            Token::Insert(def_name) => {
-                self.tokens.remove(self.position);
-                let defs = self.get_defs();
-                {
-                    let defs = defs.lock().unwrap();
-                    if let Some(def) = defs.get(&def_name) {
-                        for token in def {
-                            self.tokens.insert(self.position, token.clone());
-                        }
-                    }
-                }
+    pub fn eval(&mut self) -> Result<(), LigmaError> {
+        // a @@ out at the top just ends the program early
+        return self.run().map(|_| ());
+    }
+
+    // A REPL keeps one Fun around and hands it a chunk at a time. Unlike
+    // eval nothing gets cleaned up after, the next chunk wants those vars.
+    pub fn eval_chunk(&mut self, body: Vec<Stmt>) -> Result<(), LigmaError> {
+        let result = self.eval_block(&body);
+        return Fun::end_of_frame(result).map(|_| ());
+    }
+
+    pub fn eval_expression(&mut self, exp: &Expr) -> Result<Expr, LigmaError> {
+        return self.eval_exp(exp);
+    }
+
+    // name and parameters of everything declared so far, sorted by name
+    pub fn function_names(&self) -> Vec<(String, Vec<String>)> {
+        let ctx = self.ctx.get();
+        let mut names: Vec<(String, Vec<String>)> = ctx.functions.iter()
+            .map(|(name, declared)| (name.clone(), declared.params.clone()))
+            .collect();
+        names.sort();
+        return names;
+    }
+
+    pub fn def_names(&self) -> Vec<String> {
+        let ctx = self.ctx.get();
+        let mut names: Vec<String> = ctx.defs.keys().cloned().collect();
+        names.sort();
+        return names;
+    }
+
+    // every variable in this frame, [index]name for the indexed ones
+    pub fn var_values(&self) -> Vec<(String, Expr)> {
+        let mut values: Vec<(String, Expr)> = Vec::new();
+        for (name, slots) in &self.vars {
+            for (index, value) in slots {
+                let label = match index {
+                    Some(i) => format!("[{}]{}", i, name),
+                    None => name.clone(),
+                };
+                values.push((label, value.clone()));
             }
-            Token::Function(name) => {
-                self.tokens.remove(self.position);
-                let fun = self.skip_fun(); 
+        }
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        return values;
+    }
 
-                let funcs = self.get_funcs();
-                {
-                    let mut funcs = funcs.lock().unwrap();
-                    funcs.insert(name,fun);
-                }
+    // what falling off the end of a frame means for each way a block can finish
+    fn end_of_frame(result: Result<Flow, LigmaError>) -> Result<Option<Expr>, LigmaError> {
+        match result {
+            Ok(Flow::Break(span)) => {
+                return Err(LigmaError::unexpected("`$;` has no loop to break out of").with_span(span));
             }
-            
-            _ => {
-                //TODO: serendipity
-                self.advance();
+            Ok(Flow::Return(value)) => {
+                return Ok(value);
+            }
+            Ok(Flow::Next) => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e);
             }
-        });
+        }
+    }
+
+    // runs the body as one frame and hands back whatever @@ returned
+    fn run(&mut self) -> Result<Option<Expr>, LigmaError> {
+        let body = Arc::clone(&self.body);
+        let result = Fun::end_of_frame(self.eval_block(&body));
 
         //should work like garbage collection, idk
         //should probably add a kill function token
-        let stack = self.get_stack();
-        {
-            let mut stack = stack.lock().unwrap();
-            *stack -= 1;
-        }
+        self.ctx.get().stack -= 1;
         self.vars.clear();
         self.vars.shrink_to(0);
+        return result;
     }
 
-    fn new_sub(tokens: Vec<Token>, defs: ArcDefs, argreg: ArcArg, functions: ArcFuns, somon: SoMonitor, output: Printer) -> Fun {
-        Fun{
-            vars: HashMap::new(),
-            defs: defs,
-            argreg: argreg,
-            functions: functions,
-            tokens: tokens,
-            position: 0,
-            loop_start: Vec::new(),
-            paren_counter: Vec::new(),
-            stack: somon,
-            output: output,
-        }
-        
-    }
-     
-    pub fn new(tokens: Vec<Token>) -> Fun{
+    pub fn new(body: Vec<Stmt>, config: InterpreterConfig) -> Fun{
+        let ctx = Context {
+            argreg: HashMap::new(),
+            functions: HashMap::new(),
+            defs: HashMap::new(),
+            stack: 1,
+            fuel: None,
+            output: Box::new(BufferSink::new()),
+            written: 0,
+            input: Box::new(StdinInput),
+        };
         Fun{
+            ctx: Shared::new(ctx),
+            body: Arc::new(body),
             vars: HashMap::new(),
-            defs: Arc::new(Mutex::new(HashMap::new())),
-            argreg: Arc::new(Mutex::new(HashMap::new())),
-            functions: Arc::new(Mutex::new(HashMap::new())),
-            tokens: tokens,
-            position: 0,
-            loop_start: Vec::new(),
-            paren_counter: Vec::new(),
-            stack: Arc::new(Mutex::new(1)),
-            output: Arc::new(Mutex::new(String::new())),
+            config,
+            nesting: 0,
         }
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::ligma::files::{DiskFiles, FileProvider};
+
+// Where #[path] goes looking. A relative path is tried next to the file
+// doing the including first, then in each of search_paths in order.
+#[derive(Debug, Clone)]
+pub struct IncludeOptions {
+    // the file the source was read from, None means the top of files
+    pub path: Option<PathBuf>,
+    pub search_paths: Vec<PathBuf>,
+    pub files: Arc<dyn FileProvider>,
+}
+
+impl Default for IncludeOptions {
+    fn default() -> IncludeOptions {
+        IncludeOptions {
+            path: None,
+            search_paths: Vec::new(),
+            files: Arc::new(DiskFiles),
+        }
+    }
+}
+
+// What every lexer in one tree of includes shares. A file that's finished
+// can be included again for free, one that's still on the stack can't, that's
+// a cycle and it would never stop.
+#[derive(Debug)]
+pub struct IncludeState {
+    pub files: Arc<dyn FileProvider>,
+    pub search_paths: Vec<PathBuf>,
+    pub done: HashSet<PathBuf>,
+    // (canonical path, the name it's shown as), the file being lexed right now is last
+    pub stack: Vec<(PathBuf, String)>,
+    // lib/x and ../lib/x are the same file, it keeps whichever name it turned up as first
+    pub names: HashMap<PathBuf, String>,
+    pub graph: IncludeGraph,
+}
+
+impl Default for IncludeState {
+    fn default() -> IncludeState {
+        IncludeState {
+            files: Arc::new(DiskFiles),
+            search_paths: Vec::new(),
+            done: HashSet::new(),
+            stack: Vec::new(),
+            names: HashMap::new(),
+            graph: IncludeGraph::default(),
+        }
+    }
+}
+
+impl IncludeState {
+    pub fn name(&mut self, key: &Path, name: String) -> String {
+        return self.names.entry(key.to_path_buf()).or_insert(name).clone();
+    }
+
+    // a -> b -> a, if pulling key in would go round in a circle
+    pub fn cycle(&self, key: &Path, name: &str) -> Option<String> {
+        let at = self.stack.iter().position(|(path, _)| path == key)?;
+        let mut chain: Vec<String> = self.stack[at..].iter().map(|(_, name)| name.clone()).collect();
+        chain.push(name.to_string());
+        return Some(chain.join(" -> "));
+    }
+}
+
+// Who includes whom, starting from root. Cycles end up in here too, so the
+// graph of a broken program shows where it loops.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IncludeGraph {
+    pub root: String,
+    // (includer, included) in the order they turned up, each one once
+    pub edges: Vec<(String, String)>,
+}
+
+impl IncludeGraph {
+    pub fn add(&mut self, from: &str, to: &str) {
+        let edge = (from.to_string(), to.to_string());
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    // an indented tree, anything already shown once just gets a mention
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut shown: HashSet<&str> = HashSet::new();
+        self.walk(&self.root, 0, &mut shown, &mut text);
+        return text;
+    }
+
+    fn walk<'a>(&'a self, name: &'a str, depth: usize, shown: &mut HashSet<&'a str>, text: &mut String) {
+        text.push_str(&"  ".repeat(depth));
+        text.push_str(name);
+        if !shown.insert(name) {
+            text.push_str(" (see above)\n");
+            return;
+        }
+        text.push('\n');
+        for (from, to) in &self.edges {
+            if from == name {
+                self.walk(to, depth + 1, shown, text);
+            }
+        }
+    }
+
+    // for `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph includes {\n");
+        dot.push_str(&format!("    {};\n", dot_id(&self.root)));
+        for (from, to) in &self.edges {
+            dot.push_str(&format!("    {} -> {};\n", dot_id(from), dot_id(to)));
+        }
+        dot.push_str("}\n");
+        return dot;
+    }
+}
+
+fn dot_id(name: &str) -> String {
+    return format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ligma::files::MemoryFiles;
+    use crate::ligma::lexer::Lexer;
+
+    fn graph() -> IncludeGraph {
+        let edge = |from: &str, to: &str| (String::from(from), String::from(to));
+        return IncludeGraph {
+            root: String::from("main.ligma"),
+            edges: vec![
+                edge("main.ligma", "a.ligma"),
+                edge("main.ligma", "b.ligma"),
+                edge("a.ligma", "b.ligma"),
+                edge("b.ligma", "say \"hi\".ligma"),
+                // a cycle, which is how the lexer leaves it when it finds one
+                edge("say \"hi\".ligma", "a.ligma"),
+            ],
+        };
+    }
+
+    #[test]
+    fn text_graphs_show_each_file_once() {
+        assert_eq!(graph().to_text(), "\
+main.ligma
+  a.ligma
+    b.ligma
+      say \"hi\".ligma
+        a.ligma (see above)
+  b.ligma (see above)
+");
+    }
+
+    #[test]
+    fn dot_graphs_have_every_edge_and_quote_names() {
+        assert_eq!(graph().to_dot(), "\
+digraph includes {
+    \"main.ligma\";
+    \"main.ligma\" -> \"a.ligma\";
+    \"main.ligma\" -> \"b.ligma\";
+    \"a.ligma\" -> \"b.ligma\";
+    \"b.ligma\" -> \"say \\\"hi\\\".ligma\";
+    \"say \\\"hi\\\".ligma\" -> \"a.ligma\";
+}
+");
+    }
+
+    #[test]
+    fn the_lexer_fills_the_graph_in_as_it_goes() {
+        let mut files = MemoryFiles::new();
+        files.add("main.ligma", "#[a.ligma] #[b.ligma] #[a.ligma]");
+        files.add("a.ligma", "#[b.ligma]");
+        files.add("b.ligma", "x = 1;");
+        let options = IncludeOptions {
+            path: Some(PathBuf::from("main.ligma")),
+            files: Arc::new(files),
+            ..IncludeOptions::default()
+        };
+        let mut lexer = Lexer::new("#[a.ligma] #[b.ligma] #[a.ligma]");
+        lexer.set_includes(&options);
+        lexer.lex_all();
+        assert!(lexer.diagnostics().is_empty(), "{:?}", lexer.diagnostics());
+        assert_eq!(lexer.include_graph().to_text(), "main.ligma\n  a.ligma\n    b.ligma\n  b.ligma (see above)\n");
+    }
+}
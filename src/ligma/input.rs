@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+use crate::ligma::shared::MaybeSend;
+
+// Where ??name; gets its lines from, one a call, without the newline.
+// None means there is nothing left to read. Send too with the `sync` feature.
+pub trait InputSource: MaybeSend {
+    fn read_line(&mut self) -> Option<String>;
+}
+
+// Fun derives Debug and the sources don't need to say much
+impl fmt::Debug for dyn InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InputSource")
+    }
+}
+
+// The terminal, or whatever got piped in. Once that's closed, or it sends
+// something that isn't UTF-8, it's run out the same as a QueueInput does.
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn read_line(&mut self) -> Option<String> {
+        return read_line_from(&mut io::stdin().lock());
+    }
+}
+
+fn read_line_from(reader: &mut impl BufRead) -> Option<String> {
+    let mut input = String::new();
+    match reader.read_line(&mut input) {
+        Ok(0) | Err(_) => {
+            return None;
+        }
+        Ok(_) => {
+            return Some(input.trim().to_string());
+        }
+    }
+}
+
+// Lines handed over up front, for scripted runs. Once they're gone reading is an error.
+pub struct QueueInput {
+    lines: VecDeque<String>,
+}
+
+impl QueueInput {
+    pub fn new(lines: Vec<String>) -> QueueInput {
+        QueueInput {
+            lines: lines.into(),
+        }
+    }
+}
+
+impl InputSource for QueueInput {
+    fn read_line(&mut self) -> Option<String> {
+        return self.lines.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ligma::run::{run_with_lines, Engine, RunOptions};
+    use crate::ligma::serendipity::ErrorKind;
+
+    #[test]
+    fn programs_read_lines_in_order() {
+        for engine in [Engine::Walk, Engine::Bytecode] {
+            let result = run_with_lines("??a; ??b; c = a + b; !c; !\" \"; !a;", &["left", "right"], RunOptions { engine, ..RunOptions::default() });
+            assert!(result.ok(), "{:?}", result.errors);
+            assert_eq!(result.output, "rightleft left");
+        }
+    }
+
+    #[test]
+    fn stdin_runs_out_at_the_end() {
+        let mut piped = io::Cursor::new(b"one\n\ntwo".to_vec());
+        assert_eq!(read_line_from(&mut piped), Some(String::from("one")));
+        assert_eq!(read_line_from(&mut piped), Some(String::new()));
+        assert_eq!(read_line_from(&mut piped), Some(String::from("two")));
+        assert_eq!(read_line_from(&mut piped), None);
+        let mut garbled = io::Cursor::new(vec![0xff, 0xfe, b'\n']);
+        assert_eq!(read_line_from(&mut garbled), None);
+    }
+
+    #[test]
+    fn reading_past_the_last_line_is_an_error() {
+        for engine in [Engine::Walk, Engine::Bytecode] {
+            let result = run_with_lines("??a; !a; ??b;", &["only"], RunOptions { engine, ..RunOptions::default() });
+            assert_eq!(result.output, "only");
+            assert_eq!(result.errors[0].kind, ErrorKind::NoInput);
+        }
+    }
+}
@@ -1,16 +1,21 @@
 use std::fs;
 use std::io::Error;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::ligma::include::{IncludeGraph, IncludeOptions, IncludeState};
+use crate::ligma::serendipity::{ErrorKind, LigmaError, Span};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Include(Vec<Token>),
+    Include(Vec<Spanned>),
     SysCall(String),
     Def(String), // Done
     Insert(String), // Done
 
     Identifier(Option<String>, String), //expr
     Function(String), // Done
-    Call(String, String), //expr
+    Call(String), //expr -- the ( is eaten, args and ) follow as normal tokens
     Bool(bool), //expr
     Integer(i64), //expr
     Float(f64), //expr
@@ -31,22 +36,37 @@ pub enum Token {
     Or, //expr
     And, //expr
     Xor, //expr
+    Not, //expr -- ~ since ! is already taken by output
 
     Comment(String), //done
 
 
     LParen, //expr
     RParen, //expr
-    LBrack, //func
+    LBrack, //func -- also opens a {key: value} map
     RBrack, //func
+    LSquare, //expr -- a [ that isn't a [i]name prefix, so a list or an index
+    RSquare, //expr
+    Colon, //expr -- between a map key and its value
+    Set, //expr -- a lone = after a[key]
 
     Semicolon, //expr
+    Comma, //expr -- between call args
+
+    Return, //@@
 
     Argreg(Option<String>), //done
     Loop(Option<String>, String), //done
+    Each(String), // $name:( -- the collection is lexed as normal tokens after this
     Break, //done
     If(Option<String>, String), //done
+    IfExp, // ?( -- the condition is lexed as normal tokens after this
+    ElseIf(Option<String>, String), // ?:name{
+    ElseIfExp, // ?:(
+    Else, // ?{
+    LoopExp, // $( -- same deal
     Output(Option<String>, String), //done
+    OutputIndexed(Option<String>, String), // !name[ -- the [key]s and the ; are lexed as normal tokens after this
     OutputStr, //done
     Input(Option<String>, String), //done?
 
@@ -54,8 +74,15 @@ pub enum Token {
 
     Kill(Option<String>, String), //done -- index Option<String> is not being used for now
     EOF,
-    Illegal(usize, String),
-    //TODO: serendipity
+    Illegal(usize, ErrorKind, String), // collected into Lexer::diagnostics
+}
+
+// A token plus where it came from. Everything lex_next_token hands out is
+// wrapped in one of these so errors and dumps can point back at the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Span,
 }
 
 macro_rules! parse {
@@ -68,10 +95,18 @@ macro_rules! parse {
     };
 }
 
-
 pub struct Lexer {
     input: Vec<char>,
+    diagnostics: Vec<LigmaError>,
     position: usize,
+    // position counts chars, offset counts bytes, so spans can slice the source
+    offset: usize,
+    line: usize,
+    column: usize,
+    file: Option<String>,
+    // where this source lives on disk, if it does
+    path: Option<PathBuf>,
+    includes: IncludeState,
 }
 
 impl Lexer {
@@ -79,14 +114,69 @@ impl Lexer {
     pub fn new(input: &str) -> Lexer {
         Lexer {
             input: input.chars().collect(),
+            diagnostics: Vec::new(),
             position: 0,
+            offset: 0,
+            line: 1,
+            column: 1,
+            file: None,
+            path: None,
+            includes: IncludeState {
+                graph: IncludeGraph {
+                    root: String::from("<input>"),
+                    edges: Vec::new(),
+                },
+                ..IncludeState::default()
+            },
         }
     }
 
+    pub fn set_includes(&mut self, options: &IncludeOptions) {
+        if let Some(path) = &options.path {
+            let key = options.files.canonical(path);
+            let name = self.includes.name(&key, path.display().to_string());
+            // on the stack from the start, so a library including it back is a cycle
+            self.includes.stack.push((key, name.clone()));
+            self.includes.graph.root = name;
+            self.path = Some(path.clone());
+        }
+        self.includes.search_paths = options.search_paths.clone();
+        self.includes.files = Arc::clone(&options.files);
+    }
+
+    // everything #[path] pulled in so far, cycles included
+    pub fn include_graph(&self) -> &IncludeGraph {
+        return &self.includes.graph;
+    }
+
+    pub fn with_file(input: &str, file: String) -> Lexer {
+        let mut lexer = Lexer::new(input);
+        lexer.file = Some(file);
+        return lexer;
+    }
+
     fn advance(&mut self) {
+        if let Some(ch) = self.peek() {
+            self.offset += ch.len_utf8();
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.position += 1;
     }
 
+    // only ever used to step back over a single char that wasn't a newline
+    fn retreat(&mut self) {
+        self.position -= 1;
+        if let Some(ch) = self.peek() {
+            self.offset -= ch.len_utf8();
+            self.column -= 1;
+        }
+    }
+
     fn skip_white(&mut self) {
         parse!(|self.peek(), ch| {
             _ => {
@@ -107,56 +197,42 @@ impl Lexer {
         }
     }
 
-    fn error(&self, msg: &str) -> String {
-        let msg = format!("Error: expected {} at position {}", msg, self.position);
-        return msg;
+    // the span on the token already says where, so just say what
+    fn illegal(&self, at: usize, msg: &str) -> Token {
+        return Token::Illegal(at, ErrorKind::IllegalToken, format!("expected {}", msg));
     }
 
-    fn lex_call(&mut self, start: usize, name: String) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("an identifier for function arguments"));
-        let mut builder = String::new();
-        self.skip_white();
-        parse!(|self.peek(), ch| {
-            ')' => {
-                self.advance();
-                token = Token::Call(name, builder);
-                break;
-            }
-            _ => {
-                if ch.is_alphanumeric() || ch=='_' || ch=='@' {
-                    self.advance();
-                    builder.push(ch);
-                } else if ch.is_whitespace() {
-                    self.advance();
-                } else {
-                    break;
-                }
-            }
-        });
-        return token;
+    // a #[path] that can't be pulled in, nothing was expected of the token itself
+    fn include_error(&self, at: usize, msg: String) -> Token {
+        return Token::Illegal(at, ErrorKind::Include, msg);
     }
 
-    fn lex_identifier(&mut self, index: Option<String>) -> Token {
+    // ran out of input halfway through something, the REPL reads another line for these
+    fn unfinished(&self, at: usize, msg: &str) -> Token {
+        return Token::Illegal(at, ErrorKind::UnexpectedEof, format!("expected {}", msg));
+    }
+
+    fn lex_identifier(&mut self, mut index: Option<String>) -> Token {
         let start_position = self.position;
-        let mut token = Token::Illegal(self.position, self.error("an identifier"));
+        let mut token = self.illegal(self.position, "an identifier");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
             '{' => {
                 self.advance();
                 if index==None {
-                    token = Token::Function(builder);
+                    token = Token::Function(std::mem::take(&mut builder));
                 }else{
-                    token = Token::Illegal(self.position, self.error("a function without a array index"));
+                    token = self.illegal(self.position, "a function without a array index");
                 }
                 break;
             }
             '(' => {
                 self.advance();
                 if index==None {
-                    token = self.lex_call(start_position, builder); 
+                    token = Token::Call(std::mem::take(&mut builder));
                 }else{
-                    token = Token::Illegal(self.position, self.error("a function without a array index"));
+                    token = self.illegal(self.position, "a function without a array index");
                 }
                 break;
             }
@@ -164,11 +240,13 @@ impl Lexer {
                 self.advance();
                 if let Some(ch) = self.peek() {
                     if ch=='=' || ch=='!' {
-                        self.position -= 1;
-                        token = Token::Identifier(index, builder);
+                        self.retreat();
+                        token = Token::Identifier(index.take(), std::mem::take(&mut builder));
                     } else {
-                        token = Token::Assign(index, builder);
+                        token = Token::Assign(index.take(), std::mem::take(&mut builder));
                     }
+                } else {
+                    token = Token::Assign(index.take(), std::mem::take(&mut builder));
                 }
                 break;
             }
@@ -179,42 +257,63 @@ impl Lexer {
                 } else if ch.is_whitespace() {
                     self.advance();
                 } else {
-                    token = Token::Identifier(index, builder);
+                    token = Token::Identifier(index.take(), std::mem::take(&mut builder));
                     break;
                 }
             }
         });
+        // a name is allowed to be the very last thing, the parser can complain if it minds
+        if let Token::Illegal(..) = token {
+            if self.peek().is_none() && builder.len() > 0 {
+                token = Token::Identifier(index, builder);
+            }
+        }
         return token;
     }
 
     fn lex_number(&mut self) -> Token {
         let start_position = self.position;
         let mut is_float = false;
+        let mut malformed = false;
         let mut builder = String::new();
         parse!(|self.peek(), ch| {
             '.' => {
                 self.advance();
-                if !is_float {
-                    is_float = true;
-                    builder.push('.');
+                if is_float {
+                    malformed = true;
                 }
+                is_float = true;
+                builder.push('.');
             }
             _ => {
                 if ch.is_digit(10) {
                     self.advance();
                     builder.push(ch);
+                } else if ch.is_alphanumeric() || ch == '_' {
+                    // 12abc is neither a number nor a name, eat the whole thing
+                    self.advance();
+                    builder.push(ch);
+                    malformed = true;
                 } else {
                     break;
                 }
             }
         });
 
+        let illegal = self.illegal(start_position, &format!("a number, found `{}`", builder));
+        if malformed {
+            return illegal;
+        }
         if is_float {
-            let float_number = builder.parse::<f64>().unwrap();
-            Token::Float(float_number)
+            match builder.parse::<f64>() {
+                Ok(float_number) => Token::Float(float_number),
+                Err(_) => illegal,
+            }
         } else {
-            let integer_number = builder.parse::<i64>().unwrap();
-            Token::Integer(integer_number)
+            match builder.parse::<i64>() {
+                Ok(integer_number) => Token::Integer(integer_number),
+                Err(_) => self.illegal(start_position, &format!("a number that fits in 64 bits, found `{}`", builder)),
+            }
         }
     }
 
@@ -222,7 +321,7 @@ impl Lexer {
     // in the ligma lexer's impl lexer section
     // its 3am rn, what am i doing with my life
     fn lex_looper(&mut self, index: Option<String>) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("some sort of loop or break statement"));
+        let mut token = self.illegal(self.position, "some sort of loop or break statement");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -236,6 +335,21 @@ impl Lexer {
                 token = Token::Break;
                 break;
             }
+            '(' => {
+                // leave the ( for lex_next_token, the parser wants the whole group
+                if builder.len() == 0 && index == None {
+                    token = Token::LoopExp;
+                }
+                break;
+            }
+            ':' => {
+                self.advance();
+                if builder.len() > 0 && index == None {
+                    token = Token::Each(builder);
+                    self.skip_white();
+                }
+                break;
+            }
             _ => {
                 if ch.is_alphanumeric() || ch=='_' || ch=='@' {
                     self.advance();
@@ -251,7 +365,7 @@ impl Lexer {
     }
 
     fn lex_comment(&mut self, pos: usize) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a comment ending escape"));
+        let mut token = self.unfinished(self.position, "a comment ending escape `*/` before the end of input");
         let mut builder = String::new();
         
         parse!(|self.peek(), ch| {
@@ -291,13 +405,18 @@ impl Lexer {
 
     fn lex_if(&mut self, index: Option<String>) -> Token {
         let start_position = self.position;
-        let mut token = Token::Illegal(self.position, self.error("a expression or secondary ?"));
+        let mut token = self.illegal(self.position, "a expression or secondary ?");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
             '{' => {
                 self.advance();
-                token = Token::If(index, builder);
+                // nothing to check means it's the else on the end of a chain
+                if builder.len() == 0 && index == None {
+                    token = Token::Else;
+                } else {
+                    token = Token::If(index, builder);
+                }
                 break;
             }
             '?' => {
@@ -305,6 +424,25 @@ impl Lexer {
                 token = self.lex_input(index); 
                 break;
             }
+            ':' => {
+                self.advance();
+                if builder.len() == 0 {
+                    // ?: is just a ? that hangs off the one before it
+                    token = match self.lex_if(index) {
+                        Token::If(index, name) => Token::ElseIf(index, name),
+                        Token::IfExp => Token::ElseIfExp,
+                        Token::Else => Token::Else,
+                        _ => self.illegal(self.position, "a condition after ?:"),
+                    };
+                }
+                break;
+            }
+            '(' => {
+                if builder.len() == 0 && index == None {
+                    token = Token::IfExp;
+                }
+                break;
+            }
             _ => {
                 if ch.is_alphanumeric() || ch=='_' || ch=='@' {
                     self.advance();
@@ -321,7 +459,7 @@ impl Lexer {
 
     fn lex_string(&mut self) -> Token {
         let start_position = self.position;
-        let mut token = Token::Illegal(self.position, self.error("balenced string quotes"));
+        let mut token = self.unfinished(self.position, "a closing `\"` before the end of input");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -344,7 +482,7 @@ impl Lexer {
                     builder.push(esc);
                     self.advance();
                 } else {
-                    token = Token::Illegal(self.position, self.error("a valid escape sequence"));
+                    token = self.illegal(self.position, "a valid escape sequence");
                     break;
                 }
             }
@@ -357,12 +495,18 @@ impl Lexer {
     }
 
     fn lex_output(&mut self) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a identifier"));
+        let mut token = self.illegal(self.position, "a identifier");
         let mut builder = String::new();
         let mut index:Option<String> = None;
 
         
         parse!(|self.peek(), ch| {
+            '[' if builder.len() > 0 => {
+                // !xs[0]; indexes like xs[0] does anywhere else, the old
+                // slot is still ![0]xs;
+                token = Token::OutputIndexed(index, builder);
+                break;
+            }
             '[' => {
                 self.advance();
                 index = self.lex_array();
@@ -390,7 +534,7 @@ impl Lexer {
 
     }
     fn lex_input(&mut self, index: Option<String>) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a identifier"));
+        let mut token = self.illegal(self.position, "a identifier");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -411,7 +555,7 @@ impl Lexer {
         return token;
     }
     fn lex_logic(&mut self) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a logical expressiong"));
+        let mut token = self.illegal(self.position, "a logical expressiong");
 
         parse!(|self.peek(), ch| {
             '=' => {
@@ -424,7 +568,10 @@ impl Lexer {
                 token = Token::Neq;
                 break;
             }
-            _ => {break;}
+            _ => {
+                token = Token::Set;
+                break;
+            }
         });
         return token;
     }
@@ -460,7 +607,7 @@ impl Lexer {
     }
 
     fn lex_def(&mut self) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a def name"));
+        let mut token = self.illegal(self.position, "a def name");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -482,7 +629,7 @@ impl Lexer {
     }
 
     fn lex_insert(&mut self) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a def name"));
+        let mut token = self.illegal(self.position, "a def name");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -504,7 +651,7 @@ impl Lexer {
     }
     fn lex_syscall(&mut self) -> Token {
         let start = self.position;
-        let mut token = Token::Illegal(self.position, self.error("a syscall"));
+        let mut token = self.illegal(self.position, "a syscall");
         let mut builder = String::new();
         parse!(|self.peek(), ch| {
             '#' => {
@@ -534,6 +681,34 @@ impl Lexer {
         return fs::read_to_string(file_path);
     }
 
+    // the first place name turns up, or what to say when it's nowhere
+    fn resolve_include(&self, name: &str) -> Result<PathBuf, String> {
+        let path = Path::new(name);
+        let files = &self.includes.files;
+        if path.is_absolute() {
+            if files.exists(path) {
+                return Ok(path.to_path_buf());
+            }
+            return Err(format!("couldn't find `{}` to include", name));
+        }
+        let here = self.path.as_ref().and_then(|path| path.parent()).unwrap_or(Path::new(""));
+        let mut dirs = vec![here.to_path_buf()];
+        dirs.extend(self.includes.search_paths.iter().cloned());
+        for dir in &dirs {
+            let candidate = dir.join(path);
+            if files.exists(&candidate) {
+                return Ok(candidate);
+            }
+        }
+        let looked: Vec<String> = dirs.iter()
+            .map(|dir| match dir.as_os_str().is_empty() {
+                true => String::from("."),
+                false => dir.display().to_string(),
+            })
+            .collect();
+        return Err(format!("couldn't find `{}` to include (looked in {})", name, looked.join(", ")));
+    }
+
     fn lex_include(&mut self) -> Token {
         let start = self.position;
         let mut builder = String::new();
@@ -552,27 +727,48 @@ impl Lexer {
             }
         });
 
-        let mut tokens: Vec<Token> = Vec::new();
-        match Lexer::read_file(builder) {
-            Ok(file_contents) => {
-                let mut lexer = Lexer::new(&file_contents);
-                loop {
-                    let token = lexer.lex_next_token();
-                    if token == Token::EOF {
-                        break;
-                    }
-                    tokens.push(token);
-                }
-
-            }
-            Err(_) => {}
+        let resolved = match self.resolve_include(&builder) {
+            Ok(resolved) => resolved,
+            Err(msg) => {
+                return self.include_error(start, msg);
+            }
+        };
+        let key = self.includes.files.canonical(&resolved);
+        let name = self.includes.name(&key, resolved.display().to_string());
+        let here = match self.includes.stack.last() {
+            Some((_, here)) => here.clone(),
+            None => self.includes.graph.root.clone(),
+        };
+        self.includes.graph.add(&here, &name);
+        if let Some(chain) = self.includes.cycle(&key, &name) {
+            return self.include_error(start, format!("include cycle: {}", chain));
         }
+        if self.includes.done.contains(&key) {
+            return Token::Include(Vec::new());
+        }
+        let file_contents = match self.includes.files.read(&resolved) {
+            Ok(file_contents) => file_contents,
+            Err(e) => {
+                return self.include_error(start, format!("couldn't read `{}` to include: {}", builder, e));
+            }
+        };
+        // the included file resolves its own includes from where it lives,
+        // and shares what's already in and what's still going
+        let mut lexer = Lexer::with_file(&file_contents, name.clone());
+        lexer.path = Some(resolved);
+        lexer.includes = mem::take(&mut self.includes);
+        lexer.includes.stack.push((key.clone(), name));
+        let tokens = lexer.lex_all();
+        self.includes = mem::take(&mut lexer.includes);
+        self.includes.stack.pop();
+        self.includes.done.insert(key);
+        self.diagnostics.append(&mut lexer.diagnostics);
         let token = Token::Include(tokens);
         return token;
     }
 
     fn lex_macro(&mut self) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a def or a boolean"));
+        let mut token = self.illegal(self.position, "a def or a boolean");
 
         parse!(|self.peek(), ch| {
             '(' => {
@@ -630,8 +826,17 @@ impl Lexer {
         return index;
     } 
 
+    // can whatever comes after a [i] take an index
+    fn starts_prefixed(&mut self) -> bool {
+        self.skip_white();
+        if let Some(ch) = self.peek() {
+            return ch.is_alphanumeric() || ch=='_' || ch=='@' || ch=='?' || ch=='$' || ch=='*';
+        }
+        return false;
+    }
+
     fn lex_kill(&mut self, index: Option<String>) -> Token {
-        let mut token = Token::Illegal(self.position, self.error("a valid variable"));
+        let mut token = self.illegal(self.position, "a valid variable");
         let mut builder = String::new();
 
         parse!(|self.peek(), ch| {
@@ -654,7 +859,55 @@ impl Lexer {
         return token;
     }
 
-    pub fn lex_next_token(&mut self) -> Token {
+    pub fn lex_next_token(&mut self) -> Spanned {
+        self.skip_white();
+        let start = self.offset;
+        let line = self.line;
+        let column = self.column;
+        let token = self.lex_token();
+        let span = Span {
+            start,
+            end: self.offset,
+            line,
+            column,
+            file: self.file.clone(),
+        };
+        if let Token::Illegal(_, kind, msg) = &token {
+            let diagnostic = LigmaError::new(kind.clone(), msg.clone());
+            self.diagnostics.push(diagnostic.with_span(span.clone()));
+        }
+        return Spanned { token, span };
+    }
+
+    // Lex the whole input in one go, flattening includes. Bad tokens are kept
+    // in the stream and every one of them is also written down in
+    // diagnostics(), so callers can bail before running anything.
+    pub fn lex_all(&mut self) -> Vec<Spanned> {
+        let mut tokens: Vec<Spanned> = Vec::new();
+        loop {
+            let spanned = self.lex_next_token();
+            match spanned.token {
+                Token::EOF => {
+                    break;
+                }
+                Token::Include(to_include) => {
+                    for token_to_include in to_include {
+                        tokens.push(token_to_include);
+                    }
+                }
+                _ => {
+                    tokens.push(spanned);
+                }
+            }
+        }
+        return tokens;
+    }
+
+    pub fn diagnostics(&self) -> &Vec<LigmaError> {
+        return &self.diagnostics;
+    }
+
+    fn lex_token(&mut self) -> Token {
         let mut token = Token::EOF;
         let mut index: Option<String> = None;
 
@@ -699,6 +952,11 @@ impl Lexer {
                 token = Token::Xor;
                 break;
             }
+            '~' => {
+                self.advance();
+                token = Token::Not;
+                break;
+            }
             '+' => {
                 self.advance();
                 token = Token::Add;
@@ -752,17 +1010,45 @@ impl Lexer {
                 break;
             }
             '[' => {
+                let mark = (self.position, self.offset, self.line, self.column);
                 self.advance();
                 index = self.lex_array();
-                continue;
+                // [i]name is the old index prefix, any other [ is a list or an a[key]
+                if index.is_some() && self.starts_prefixed() {
+                    continue;
+                }
+                (self.position, self.offset, self.line, self.column) = mark;
+                self.advance();
+                token = Token::LSquare;
+                break;
+            }
+            ']' => {
+                self.advance();
+                token = Token::RSquare;
+                break;
+            }
+            ':' => {
+                self.advance();
+                token = Token::Colon;
+                break;
             }
             ';' => {
                 self.advance();
                 token = Token::Semicolon;
                 break;
             }
+            ',' => {
+                self.advance();
+                token = Token::Comma;
+                break;
+            }
             '@' => {
                 self.advance();
+                if let Some('@') = self.peek() {
+                    self.advance();
+                    token = Token::Return;
+                    break;
+                }
                 self.skip_white();
                 if let Some(ch) = self.peek() {
                     if ch == '=' {
@@ -798,7 +1084,7 @@ impl Lexer {
                     token = self.lex_identifier(index);
                     break;
                 } else { self.advance();
-                    token = Token::Illegal(self.position, self.error("a valid token"));
+                    token = self.illegal(self.position, "a valid token");
                     break;
                 }
             }
@@ -808,3 +1094,88 @@ impl Lexer {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ligma::files::MemoryFiles;
+    use crate::ligma::run::{run_source, RunOptions};
+
+    fn kinds(source: &str) -> Vec<ErrorKind> {
+        let mut lexer = Lexer::new(source);
+        lexer.lex_all();
+        return lexer.diagnostics().iter().map(|diagnostic| diagnostic.kind.clone()).collect();
+    }
+
+    #[test]
+    fn running_out_of_input_is_its_own_kind() {
+        assert_eq!(kinds("a = \"abc"), vec![ErrorKind::UnexpectedEof]);
+        assert_eq!(kinds("/* still going"), vec![ErrorKind::UnexpectedEof]);
+        assert_eq!(kinds("$x"), vec![ErrorKind::IllegalToken]);
+        assert_eq!(kinds("a = \"abc\"; /* done */"), vec![]);
+    }
+
+    #[test]
+    fn every_bad_token_is_reported_in_one_go() {
+        let mut lexer = Lexer::new("a = 1;\n$x b = 2;\nc = 3 ` 4;\n?:\n$y\n");
+        lexer.lex_all();
+        let found: Vec<(usize, String)> = lexer.diagnostics().iter()
+            .map(|diagnostic| (diagnostic.span.as_ref().unwrap().line, diagnostic.message.clone()))
+            .collect();
+        assert_eq!(found, vec![
+            (2, String::from("expected some sort of loop or break statement")),
+            (3, String::from("expected a valid token")),
+            (4, String::from("expected a condition after ?:")),
+            (5, String::from("expected some sort of loop or break statement")),
+        ]);
+        // and none of it runs, not even the good first line
+        let result = run_source("!\"hi\";\n$x b = 2;\nc = 3 ` 4;", RunOptions::default());
+        assert!(!result.ran);
+        assert_eq!(result.output, "");
+        assert_eq!(result.errors.len(), 2);
+    }
+
+    fn at(start: usize, end: usize, line: usize, column: usize, file: Option<&str>) -> Span {
+        return Span { start, end, line, column, file: file.map(String::from) };
+    }
+
+    #[test]
+    fn spans_count_bytes_for_offsets_and_chars_for_columns() {
+        let mut lexer = Lexer::new("a = 1;\n  \u{e9} = \"\u{fc}\"; !\u{e9};\nb = 2;");
+        let spans: Vec<Span> = lexer.lex_all().into_iter().map(|spanned| spanned.span).collect();
+        assert_eq!(spans, vec![
+            at(0, 3, 1, 1, None),
+            at(4, 5, 1, 5, None),
+            at(5, 6, 1, 6, None),
+            // é and ü are two bytes each but one column
+            at(9, 13, 2, 3, None),
+            at(14, 18, 2, 7, None),
+            at(18, 19, 2, 10, None),
+            at(20, 24, 2, 12, None),
+            at(25, 28, 3, 1, None),
+            at(29, 30, 3, 5, None),
+            at(30, 31, 3, 6, None),
+        ]);
+        let source = "a = 1;\n  \u{e9} = \"\u{fc}\";";
+        assert_eq!(&source[9..13], "\u{e9} =");
+        assert_eq!(&source[14..18], "\"\u{fc}\"");
+    }
+
+    #[test]
+    fn included_tokens_say_which_file_they_came_from() {
+        let mut files = MemoryFiles::new();
+        files.add("lib.ligma", "\n  zz = 1;");
+        let mut lexer = Lexer::new("#[lib.ligma] b = 2;");
+        lexer.set_includes(&IncludeOptions { files: Arc::new(files), ..IncludeOptions::default() });
+        let spans: Vec<Span> = lexer.lex_all().into_iter().map(|spanned| spanned.span).collect();
+        assert_eq!(spans, vec![
+            // counted from the top of lib.ligma, not from where it was included
+            at(3, 7, 2, 3, Some("lib.ligma")),
+            at(8, 9, 2, 8, Some("lib.ligma")),
+            at(9, 10, 2, 9, Some("lib.ligma")),
+            at(13, 16, 1, 14, None),
+            at(17, 18, 1, 18, None),
+            at(18, 19, 1, 19, None),
+        ]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::io::{self, Write};
+use crate::ligma::shared::MaybeSend;
+
+// Where !name; and !"text"; end up, each chunk the moment it's printed.
+// With the `sync` feature they have to be Send as well.
+pub trait OutputSink: MaybeSend {
+    fn write(&mut self, chunk: &str);
+
+    // whatever the sink held on to, the streaming ones already let it all go
+    fn contents(&self) -> String {
+        return String::new();
+    }
+}
+
+impl fmt::Debug for dyn OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OutputSink")
+    }
+}
+
+// The terminal, flushed after every chunk so you can watch it go.
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, chunk: &str) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(chunk.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+// The old behaviour, keep it all and read it back after.
+#[derive(Default)]
+pub struct BufferSink {
+    buffer: String,
+}
+
+impl BufferSink {
+    pub fn new() -> BufferSink {
+        BufferSink {
+            buffer: String::new(),
+        }
+    }
+}
+
+impl OutputSink for BufferSink {
+    fn write(&mut self, chunk: &str) {
+        self.buffer.push_str(chunk);
+    }
+
+    fn contents(&self) -> String {
+        return self.buffer.clone();
+    }
+}
+
+// A buffer that stops growing at limit bytes, for when a runaway loop
+// shouldn't be allowed to eat all the memory on the page.
+pub struct CappedSink {
+    buffer: String,
+    limit: usize,
+    truncated: bool,
+}
+
+impl CappedSink {
+    pub fn new(limit: usize) -> CappedSink {
+        CappedSink {
+            buffer: String::new(),
+            limit,
+            truncated: false,
+        }
+    }
+}
+
+impl OutputSink for CappedSink {
+    fn write(&mut self, chunk: &str) {
+        if self.truncated {
+            return;
+        }
+        let room = self.limit - self.buffer.len();
+        if chunk.len() <= room {
+            self.buffer.push_str(chunk);
+            return;
+        }
+        self.buffer.push_str(fit(chunk, room));
+        self.truncated = true;
+    }
+
+    fn contents(&self) -> String {
+        if self.truncated {
+            return format!("{}{}", self.buffer, cut_off(self.limit));
+        }
+        return self.buffer.clone();
+    }
+}
+
+// as much of chunk as fits in room bytes without cutting a char in half
+pub fn fit(chunk: &str, room: usize) -> &str {
+    let mut end = room.min(chunk.len());
+    while !chunk.is_char_boundary(end) {
+        end -= 1;
+    }
+    return &chunk[..end];
+}
+
+// what goes on the end of output that didn't all fit
+pub fn cut_off(limit: usize) -> String {
+    return format!("\n[output cut off after {} bytes]\n", limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capped_sinks_stop_at_the_limit() {
+        let mut sink = CappedSink::new(5);
+        sink.write("abc");
+        assert_eq!(sink.contents(), "abc");
+        sink.write("defg");
+        sink.write("more");
+        assert_eq!(sink.contents(), format!("abcde{}", cut_off(5)));
+    }
+
+    #[test]
+    fn capped_sinks_never_cut_a_char_in_half() {
+        // \u{e9} is two bytes, so only the a fits in 2
+        let mut sink = CappedSink::new(2);
+        sink.write("a\u{e9}");
+        assert_eq!(sink.contents(), format!("a{}", cut_off(2)));
+        assert_eq!(fit("\u{e9}\u{e9}", 3), "\u{e9}");
+        assert_eq!(fit("abc", 10), "abc");
+    }
+}
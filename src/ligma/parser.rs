@@ -0,0 +1,799 @@
+use crate::Expr;
+use crate::Token;
+use crate::ligma::lexer::Spanned;
+use std::sync::Arc;
+use crate::ligma::stmt::{DefBody, Stmt};
+use crate::ligma::serendipity::{ErrorKind, LigmaError, Span};
+use crate::ligma::config::InterpreterConfig;
+
+macro_rules! parse {
+    (|$self:ident.$peek:ident(), $token:ident| { $($body:tt)* }) => {
+        while let Some($token) = $self.$peek() {
+            match $token {
+                $($body)*
+            }
+        }
+    };
+}
+
+// Turns the flat token vector into statements once, up front, so Fun never
+// has to count braces while it's running.
+pub struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    // old scripts were written for an interpreter that folded every operator
+    // strictly left to right, #left_to_right# (or this flag) gets that back
+    left_to_right: bool,
+    // every ( and every { is another few Rust calls deep, so 200k of them
+    // would blow the stack long before Fun got to count anything
+    nesting: usize,
+    blocks: usize,
+    max_nesting: usize,
+}
+
+pub const LEFT_TO_RIGHT_PRAGMA: &str = "left_to_right";
+pub const PRECEDENCE_PRAGMA: &str = "precedence";
+
+impl Parser {
+    pub fn new(tokens: Vec<Spanned>) -> Parser {
+        // comments never mean anything past this point
+        let tokens = tokens.into_iter()
+            .filter(|spanned| !matches!(spanned.token, Token::Comment(_)))
+            .collect();
+        Parser {
+            tokens,
+            position: 0,
+            left_to_right: false,
+            nesting: 0,
+            blocks: 0,
+            max_nesting: InterpreterConfig::default().max_expr_nesting,
+        }
+    }
+
+    pub fn set_left_to_right(&mut self, left_to_right: bool) {
+        self.left_to_right = left_to_right;
+    }
+
+    // blocks and expressions each get this many levels, same as Fun allows
+    pub fn set_max_nesting(&mut self, max_nesting: usize) {
+        self.max_nesting = max_nesting;
+    }
+
+    // whether going one level further in than depth is too far
+    fn too_deep(&self, depth: usize, what: &str) -> Result<(), LigmaError> {
+        if depth >= self.max_nesting {
+            let msg = format!("{} nests deeper than {} levels", what, self.max_nesting);
+            return Err(LigmaError::new(ErrorKind::LimitExceeded, msg).with_span(self.span()));
+        }
+        return Ok(());
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, LigmaError> {
+        let stmts = self.parse_block()?;
+        // parse_block only stops early on a `}` nobody opened
+        if self.peek().is_some() {
+            return Err(self.error("unexpected `}` without a matching block"));
+        }
+        return Ok(stmts);
+    }
+
+    // the whole stream as one expression, so a REPL can echo `a + 1`
+    pub fn parse_expression(&mut self) -> Result<Expr, LigmaError> {
+        let exp = self.parse_exp()?;
+        if self.peek().is_some() {
+            return Err(self.error("expected the end of the expression"));
+        }
+        return Ok(exp);
+    }
+
+    fn peek(&self) -> Option<Token> {
+        if let Some(spanned) = self.tokens.get(self.position) {
+            return Some(spanned.token.clone());
+        }
+        return None;
+    }
+
+    fn advance(&mut self) {
+        self.position += 1;
+    }
+
+    // where the current token is, or the last one if we ran off the end
+    fn span(&self) -> Span {
+        let position = self.position.min(self.tokens.len());
+        if let Some(spanned) = self.tokens.get(position) {
+            return spanned.span.clone();
+        }
+        if position > 0 {
+            return self.tokens[position - 1].span.clone();
+        }
+        return Span {
+            start: 0,
+            end: 0,
+            line: 1,
+            column: 1,
+            file: None,
+        };
+    }
+
+    fn error(&self, msg: &str) -> LigmaError {
+        return LigmaError::unexpected(msg).with_span(self.span());
+    }
+
+    fn index_exp(index: Option<String>, span: &Span) -> Option<Expr> {
+        return index.map(|name| Expr::Var(None, name, span.clone()));
+    }
+
+    fn var_exp(index: Option<String>, name: String, span: &Span) -> Expr {
+        let index = Parser::index_exp(index, span).map(Box::new);
+        return Expr::Var(index, name, span.clone());
+    }
+
+    // statements up to a `}` (left for the caller) or the end of input
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, LigmaError> {
+        let mut stmts: Vec<Stmt> = Vec::new();
+        parse!(|self.peek(), token| {
+            Token::RBrack => {
+                break;
+            }
+            Token::SysCall(call) => {
+                self.advance();
+                if call == LEFT_TO_RIGHT_PRAGMA {
+                    self.left_to_right = true;
+                } else if call == PRECEDENCE_PRAGMA {
+                    self.left_to_right = false;
+                }
+            }
+            Token::Semicolon => {
+                self.advance();
+            }
+            _ => {
+                let stmt = self.parse_stmt()?;
+                stmts.push(stmt);
+            }
+        });
+        return Ok(stmts);
+    }
+
+    // the `{` was already swallowed by the lexer as part of ?, $ or name{
+    fn parse_body(&mut self, open: &Span) -> Result<Vec<Stmt>, LigmaError> {
+        self.too_deep(self.blocks, "block")?;
+        self.blocks += 1;
+        let body = self.parse_block();
+        self.blocks -= 1;
+        let body = body?;
+        match self.peek() {
+            Some(Token::RBrack) => {
+                self.advance();
+                return Ok(body);
+            }
+            _ => {
+                return Err(LigmaError::unexpected("expected a `}` to close this block").with_span(open.clone()));
+            }
+        }
+    }
+
+    // whatever hangs off the end of an if block. ?:cond{ } turns into an else
+    // holding just another if, so a chain is ifs nested down the else side
+    // and every link counts as a block
+    fn parse_else(&mut self) -> Result<Option<Vec<Stmt>>, LigmaError> {
+        self.too_deep(self.blocks, "else chain")?;
+        self.blocks += 1;
+        let otherwise = self.parse_else_inner();
+        self.blocks -= 1;
+        return otherwise;
+    }
+
+    fn parse_else_inner(&mut self) -> Result<Option<Vec<Stmt>>, LigmaError> {
+        let span = self.span();
+        match self.peek() {
+            Some(Token::Else) => {
+                self.advance();
+                let body = self.parse_body(&span)?;
+                return Ok(Some(body));
+            }
+            Some(Token::ElseIf(index, name)) => {
+                self.advance();
+                let cond = Parser::var_exp(index, name, &span);
+                let body = self.parse_body(&span)?;
+                let otherwise = self.parse_else()?;
+                return Ok(Some(vec![Stmt::If(cond, body, otherwise, span)]));
+            }
+            Some(Token::ElseIfExp) => {
+                self.advance();
+                let cond = self.parse_condition(&span)?;
+                let body = self.parse_body(&span)?;
+                let otherwise = self.parse_else()?;
+                return Ok(Some(vec![Stmt::If(cond, body, otherwise, span)]));
+            }
+            _ => {
+                return Ok(None);
+            }
+        }
+    }
+
+    // ?( and $( are followed by a parenthesised expression and then the {
+    // that opens the body, which the lexer couldn't glue on this time
+    fn parse_condition(&mut self, open: &Span) -> Result<Expr, LigmaError> {
+        match self.peek() {
+            Some(Token::LParen) => {}
+            _ => {
+                return Err(self.error("expected a `(` to start the condition"));
+            }
+        }
+        let cond = self.parse_operand()?;
+        match self.peek() {
+            Some(Token::LBrack) => {
+                self.advance();
+                return Ok(cond);
+            }
+            _ => {
+                return Err(LigmaError::unexpected("expected a `{` after the condition").with_span(open.clone()));
+            }
+        }
+    }
+
+    // a def's tokens get parsed on their own by a fresh Parser, right here
+    // and only once, so inserting it later is just running what's stored
+    fn parse_def(&mut self, open: &Span) -> Result<DefBody, LigmaError> {
+        match self.peek() {
+            Some(Token::LBrack) => {
+                self.advance();
+            }
+            _ => {
+                return Err(self.error("expected a `{` after the def name"));
+            }
+        }
+        let mut paren = 1;
+        let mut tokens: Vec<Spanned> = Vec::new();
+        // the def gets parsed by a fresh Parser, so carry the mode along
+        if self.left_to_right {
+            let pragma = Token::SysCall(LEFT_TO_RIGHT_PRAGMA.to_string());
+            tokens.push(Spanned { token: pragma, span: open.clone() });
+        }
+        parse!(|self.peek(), token| {
+            Token::Function(_) | Token::If(_,_) | Token::Loop(_,_) | Token::ElseIf(_,_) | Token::Else | Token::LBrack => {
+                paren += 1;
+                tokens.push(self.tokens[self.position].clone());
+                self.advance();
+            }
+            Token::RBrack => {
+                paren -= 1;
+                if paren == 0 {
+                    self.advance();
+                    let mut parser = Parser::new(tokens);
+                    parser.set_max_nesting(self.max_nesting);
+                    return Ok(Arc::new(parser.parse()));
+                }
+                tokens.push(self.tokens[self.position].clone());
+                self.advance();
+            }
+            _ => {
+                tokens.push(self.tokens[self.position].clone());
+                self.advance();
+            }
+        });
+        return Err(LigmaError::unexpected("expected a `}` to close this def").with_span(open.clone()));
+    }
+
+    // the lexer ate the `(` with the name, so this is everything up to the `)`
+    fn parse_args(&mut self, open: &Span) -> Result<Vec<Expr>, LigmaError> {
+        let mut args: Vec<Expr> = Vec::new();
+        if let Some(Token::RParen) = self.peek() {
+            self.advance();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_binary(1)?);
+            match self.peek() {
+                Some(Token::Comma) => {
+                    self.advance();
+                }
+                Some(Token::RParen) => {
+                    self.advance();
+                    return Ok(args);
+                }
+                _ => {
+                    return Err(LigmaError::unexpected("expected a `,` or a `)` to close this call").with_span(open.clone()));
+                }
+            }
+        }
+    }
+
+    // parameters have to be plain names, f(a + 1){ doesn't mean anything
+    fn params(args: Vec<Expr>, span: &Span) -> Result<Vec<String>, LigmaError> {
+        let mut params: Vec<String> = Vec::new();
+        for arg in args {
+            match arg {
+                Expr::Var(None, name, _) => {
+                    if params.contains(&name) {
+                        let msg = format!("parameter `{}` is named twice", name);
+                        return Err(LigmaError::unexpected(&msg).with_span(span.clone()));
+                    }
+                    params.push(name);
+                }
+                _ => {
+                    return Err(LigmaError::unexpected("expected a parameter name").with_span(span.clone()));
+                }
+            }
+        }
+        return Ok(params);
+    }
+
+    fn parse_stmt(&mut self) -> Result<Stmt, LigmaError> {
+        let span = self.span();
+        let token = match self.peek() {
+            Some(token) => token,
+            None => {
+                return Err(self.error("unexpected end of input"));
+            }
+        };
+        self.advance();
+        match token {
+            Token::Assign(index, name) => {
+                let index = Parser::index_exp(index, &span);
+                let value = self.parse_exp()?;
+                return Ok(Stmt::Assign(index, name, value, span));
+            }
+            Token::Identifier(index, name) => {
+                // the only statement that starts with a bare name is a[key] = value;
+                let index = Parser::index_exp(index, &span);
+                let mut keys: Vec<Expr> = Vec::new();
+                parse!(|self.peek(), token| {
+                    Token::LSquare => {
+                        keys.push(self.parse_key()?);
+                    }
+                    _ => {
+                        break;
+                    }
+                });
+                match self.peek() {
+                    Some(Token::Set) if keys.len() > 0 => {
+                        self.advance();
+                    }
+                    _ => {
+                        return Err(LigmaError::unexpected("expected an assignment like `a[key] = value;`").with_span(span));
+                    }
+                }
+                let value = self.parse_exp()?;
+                return Ok(Stmt::SetIndex(index, name, keys, value, span));
+            }
+            Token::Argreg(index) => {
+                let index = Parser::index_exp(index, &span);
+                let value = self.parse_exp()?;
+                return Ok(Stmt::Argreg(index, value, span));
+            }
+            Token::If(index, name) => {
+                let cond = Parser::var_exp(index, name, &span);
+                let body = self.parse_body(&span)?;
+                let otherwise = self.parse_else()?;
+                return Ok(Stmt::If(cond, body, otherwise, span));
+            }
+            Token::Loop(index, name) => {
+                let cond = Parser::var_exp(index, name, &span);
+                let body = self.parse_body(&span)?;
+                return Ok(Stmt::Loop(cond, body, span));
+            }
+            Token::IfExp => {
+                let cond = self.parse_condition(&span)?;
+                let body = self.parse_body(&span)?;
+                let otherwise = self.parse_else()?;
+                return Ok(Stmt::If(cond, body, otherwise, span));
+            }
+            Token::ElseIf(_,_) | Token::ElseIfExp | Token::Else => {
+                return Err(LigmaError::unexpected("this else has no `?` block before it").with_span(span));
+            }
+            Token::LoopExp => {
+                let cond = self.parse_condition(&span)?;
+                let body = self.parse_body(&span)?;
+                return Ok(Stmt::Loop(cond, body, span));
+            }
+            Token::Each(name) => {
+                let collection = self.parse_condition(&span)?;
+                let body = self.parse_body(&span)?;
+                return Ok(Stmt::Each(name, collection, body, span));
+            }
+            Token::Break => {
+                return Ok(Stmt::Break(span));
+            }
+            Token::Output(index, name) => {
+                return Ok(Stmt::Output(Parser::var_exp(index, name, &span), span));
+            }
+            Token::OutputIndexed(index, name) => {
+                // !xs[0][1]; the [ is still to come and so is the ;
+                let mut exp = Parser::var_exp(index, name, &span);
+                parse!(|self.peek(), token| {
+                    Token::LSquare => {
+                        let key_span = self.span();
+                        let key = self.parse_key()?;
+                        exp = Expr::Index(Box::new(exp), Box::new(key), key_span);
+                    }
+                    _ => {
+                        break;
+                    }
+                });
+                match self.peek() {
+                    Some(Token::Semicolon) => {
+                        self.advance();
+                    }
+                    _ => {
+                        return Err(self.error("expected a `;` after what to output"));
+                    }
+                }
+                return Ok(Stmt::Output(exp, span));
+            }
+            Token::OutputStr => {
+                // !"a"; "b"; keeps printing for as long as strings keep coming
+                let mut strings: Vec<String> = Vec::new();
+                parse!(|self.peek(), token| {
+                    Token::String(to_print) => {
+                        self.advance();
+                        strings.push(to_print);
+                        parse!(|self.peek(), token| {
+                            Token::Semicolon => {
+                                self.advance();
+                            }
+                            _ => {
+                                break;
+                            }
+                        });
+                    }
+                    _ => {
+                        break;
+                    }
+                });
+                if strings.len() == 0 {
+                    return Err(self.error("expected a string to output"));
+                }
+                return Ok(Stmt::OutputStr(strings, span));
+            }
+            Token::Input(index, name) => {
+                return Ok(Stmt::Input(Parser::index_exp(index, &span), name, span));
+            }
+            Token::Kill(_, name) => {
+                return Ok(Stmt::Kill(name, span));
+            }
+            Token::Function(name) => {
+                let body = self.parse_body(&span)?;
+                return Ok(Stmt::Function(name, Vec::new(), body, span));
+            }
+            Token::Call(name) => {
+                let args = self.parse_args(&span)?;
+                // name(a, b){ is a definition wearing a call's clothes
+                if let Some(Token::LBrack) = self.peek() {
+                    self.advance();
+                    let params = Parser::params(args, &span)?;
+                    let body = self.parse_body(&span)?;
+                    return Ok(Stmt::Function(name, params, body, span));
+                }
+                return Ok(Stmt::Call(name, args, span));
+            }
+            Token::Return => {
+                // @@; leaves with nothing, @@ value; leaves with value
+                match self.peek() {
+                    Some(Token::Semicolon) => {
+                        self.advance();
+                        return Ok(Stmt::Return(None, span));
+                    }
+                    Some(Token::RBrack) | None => {
+                        return Ok(Stmt::Return(None, span));
+                    }
+                    _ => {
+                        let value = self.parse_exp()?;
+                        return Ok(Stmt::Return(Some(value), span));
+                    }
+                }
+            }
+            Token::Def(name) => {
+                let body = self.parse_def(&span)?;
+                return Ok(Stmt::Def(name, body, span));
+            }
+            Token::Insert(name) => {
+                return Ok(Stmt::Insert(name, span));
+            }
+            Token::Illegal(_, kind, msg) => {
+                return Err(LigmaError::new(kind, msg).with_span(span));
+            }
+            token => {
+                return Err(LigmaError::unexpected(&format!("unexpected {:?}", token)).with_span(span));
+            }
+        }
+    }
+
+    // A `;` ends an expression and gets eaten. Anything else that can't carry
+    // on an expression also ends it, but is left for the next statement.
+    fn parse_exp(&mut self) -> Result<Expr, LigmaError> {
+        if let Some(Token::Semicolon) = self.peek() {
+            // a = ; has always meant false
+            self.advance();
+            return Ok(Expr::Bool(false));
+        }
+        let exp = self.parse_binary(1)?;
+        if let Some(Token::Semicolon) = self.peek() {
+            self.advance();
+        }
+        return Ok(exp);
+    }
+
+    // bigger binds tighter, None means it isn't a binary operator at all
+    fn precedence(token: &Token) -> Option<u8> {
+        match token {
+            Token::Or => Some(1),
+            Token::Xor => Some(2),
+            Token::And => Some(3),
+            Token::Eq | Token::Neq | Token::Lt | Token::Gt | Token::LtEq | Token::GtEq => Some(4),
+            Token::Add | Token::Sub => Some(5),
+            Token::Multiply | Token::Divide | Token::Mod => Some(6),
+            _ => None,
+        }
+    }
+
+    // precedence climbing, everything is left associative
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, LigmaError> {
+        let mut exp = self.parse_operand()?;
+        parse!(|self.peek(), token| {
+            _ => {
+                let precedence = match Parser::precedence(&token) {
+                    Some(precedence) => precedence,
+                    None => {
+                        break;
+                    }
+                };
+                if precedence < min_precedence {
+                    break;
+                }
+                let span = self.span();
+                self.advance();
+                let rhs: Expr;
+                if self.left_to_right {
+                    // never climb, so a + b * c is (a + b) * c
+                    rhs = self.parse_operand()?;
+                } else {
+                    rhs = self.parse_binary(precedence + 1)?;
+                }
+                exp = Expr::BinOp(Box::new(exp), token, Box::new(rhs), span);
+            }
+        });
+        return Ok(exp);
+    }
+
+    // every way an expression can hold another one comes back through here,
+    // so this is where the depth gets counted
+    fn parse_operand(&mut self) -> Result<Expr, LigmaError> {
+        self.too_deep(self.nesting, "expression")?;
+        self.nesting += 1;
+        let exp = self.parse_indexed();
+        self.nesting -= 1;
+        return exp;
+    }
+
+    // [key] after a value, as many times as they keep coming
+    fn parse_indexed(&mut self) -> Result<Expr, LigmaError> {
+        let mut exp = self.parse_primary()?;
+        parse!(|self.peek(), token| {
+            Token::LSquare => {
+                let span = self.span();
+                let key = self.parse_key()?;
+                exp = Expr::Index(Box::new(exp), Box::new(key), span);
+            }
+            _ => {
+                break;
+            }
+        });
+        return Ok(exp);
+    }
+
+    fn parse_key(&mut self) -> Result<Expr, LigmaError> {
+        let open = self.span();
+        self.advance();
+        let key = self.parse_binary(1)?;
+        match self.peek() {
+            Some(Token::RSquare) => {
+                self.advance();
+                return Ok(key);
+            }
+            _ => {
+                return Err(LigmaError::unexpected("expected a `]` to close this `[`").with_span(open));
+            }
+        }
+    }
+
+    // [a, b] and {key: value}, the opening bracket is already eaten
+    fn parse_list(&mut self, open: &Span) -> Result<Vec<Expr>, LigmaError> {
+        let mut items: Vec<Expr> = Vec::new();
+        parse!(|self.peek(), token| {
+            Token::RSquare => {
+                self.advance();
+                return Ok(items);
+            }
+            _ => {
+                items.push(self.parse_binary(1)?);
+                match self.peek() {
+                    Some(Token::Comma) => {
+                        self.advance();
+                    }
+                    Some(Token::RSquare) => {}
+                    _ => {
+                        break;
+                    }
+                }
+            }
+        });
+        return Err(LigmaError::unexpected("expected a `,` or a `]` to close this list").with_span(open.clone()));
+    }
+
+    fn parse_map(&mut self, open: &Span) -> Result<Vec<(Expr, Expr)>, LigmaError> {
+        let mut entries: Vec<(Expr, Expr)> = Vec::new();
+        parse!(|self.peek(), token| {
+            Token::RBrack => {
+                self.advance();
+                return Ok(entries);
+            }
+            _ => {
+                let key = self.parse_binary(1)?;
+                match self.peek() {
+                    Some(Token::Colon) => {
+                        self.advance();
+                    }
+                    _ => {
+                        return Err(self.error("expected a `:` between the key and the value"));
+                    }
+                }
+                let value = self.parse_binary(1)?;
+                entries.push((key, value));
+                match self.peek() {
+                    Some(Token::Comma) => {
+                        self.advance();
+                    }
+                    Some(Token::RBrack) => {}
+                    _ => {
+                        break;
+                    }
+                }
+            }
+        });
+        return Err(LigmaError::unexpected("expected a `,` or a `}` to close this map").with_span(open.clone()));
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, LigmaError> {
+        let span = self.span();
+        let token = match self.peek() {
+            Some(token) => token,
+            None => {
+                let e = LigmaError::new(ErrorKind::UnexpectedEof, String::from("expected a value before the end of input"));
+                return Err(e.with_span(self.span()));
+            }
+        };
+        match token {
+            Token::Identifier(index, name) => {
+                self.advance();
+                return Ok(Parser::var_exp(index, name, &span));
+            }
+            Token::Integer(val) => {
+                self.advance();
+                return Ok(Expr::Int(val));
+            }
+            Token::Float(val) => {
+                self.advance();
+                return Ok(Expr::Float(val));
+            }
+            Token::Bool(val) => {
+                self.advance();
+                return Ok(Expr::Bool(val));
+            }
+            Token::String(val) => {
+                self.advance();
+                return Ok(Expr::String(val));
+            }
+            Token::Argreg(index) => {
+                self.advance();
+                let index = Parser::index_exp(index, &span).map(Box::new);
+                return Ok(Expr::Argreg(index, span));
+            }
+            Token::Call(name) => {
+                self.advance();
+                let args = self.parse_args(&span)?;
+                return Ok(Expr::Call(name, args, span));
+            }
+            Token::Sub | Token::Add | Token::Not => {
+                // prefix ops grab just the operand after them, so -a * b is (-a) * b
+                self.advance();
+                let exp = self.parse_operand()?;
+                return Ok(Expr::UnOp(token, Box::new(exp)));
+            }
+            Token::LSquare => {
+                self.advance();
+                let items = self.parse_list(&span)?;
+                return Ok(Expr::List(items));
+            }
+            Token::LBrack => {
+                self.advance();
+                let entries = self.parse_map(&span)?;
+                return Ok(Expr::MapLit(entries));
+            }
+            Token::LParen => {
+                self.advance();
+                let exp = self.parse_binary(1)?;
+                match self.peek() {
+                    Some(Token::RParen) => {
+                        self.advance();
+                        return Ok(exp);
+                    }
+                    _ => {
+                        return Err(LigmaError::unexpected("expected a `)` to close this `(`").with_span(span));
+                    }
+                }
+            }
+            Token::Illegal(_, kind, msg) => {
+                return Err(LigmaError::new(kind, msg).with_span(span));
+            }
+            token => {
+                if Parser::precedence(&token).is_some() {
+                    return Err(self.error("expected a value before the operator"));
+                }
+                return Err(self.error(&format!("expected a value, found {:?}", token)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ligma::lexer::Lexer;
+    use std::thread;
+
+    fn parse(source: &str, max_nesting: usize) -> Result<Vec<Stmt>, LigmaError> {
+        let mut parser = Parser::new(Lexer::new(source).lex_all());
+        parser.set_max_nesting(max_nesting);
+        return parser.parse();
+    }
+
+    // the default limit on the stack the wasm build gets, which a debug
+    // build needs a few MB of to get 300 levels in
+    fn parse_big(source: String) -> Result<Vec<Stmt>, LigmaError> {
+        let parsing = thread::Builder::new().stack_size(16 * 1024 * 1024).spawn(move || {
+            return Parser::new(Lexer::new(&source).lex_all()).parse();
+        });
+        return parsing.unwrap().join().unwrap();
+    }
+
+    fn kind(result: Result<Vec<Stmt>, LigmaError>) -> ErrorKind {
+        match result {
+            Ok(_) => {
+                panic!("expected it not to parse");
+            }
+            Err(e) => {
+                return e.kind;
+            }
+        }
+    }
+
+    #[test]
+    fn nesting_stops_at_the_limit() {
+        assert!(parse("x = ((1));", 3).is_ok());
+        assert_eq!(kind(parse("x = (((1)));", 3)), ErrorKind::LimitExceeded);
+        assert!(parse("x = --1;", 3).is_ok());
+        assert_eq!(kind(parse("x = ---1;", 3)), ErrorKind::LimitExceeded);
+        assert!(parse("a = 1; ?a{ ?a{ ?a{ } } }", 3).is_ok());
+        assert_eq!(kind(parse("a = 1; ?a{ ?a{ ?a{ ?a{ } } } }", 3)), ErrorKind::LimitExceeded);
+        assert_eq!(kind(parse("a = 1; ?a{ }?:a{ }?:a{ }?:a{ }", 3)), ErrorKind::LimitExceeded);
+    }
+
+    #[test]
+    fn absurd_nesting_is_an_error_and_not_a_crash() {
+        let n = 200000;
+        let parens = format!("x = {}1{};", "(".repeat(n), ")".repeat(n));
+        assert_eq!(kind(parse_big(parens)), ErrorKind::LimitExceeded);
+        let minuses = format!("x = {}1;", "-".repeat(n));
+        assert_eq!(kind(parse_big(minuses)), ErrorKind::LimitExceeded);
+        let blocks = format!("a = 1; {}{}", "?a{".repeat(n), "}".repeat(n));
+        assert_eq!(kind(parse_big(blocks)), ErrorKind::LimitExceeded);
+        let chain = format!("a = 1; ?a{{ }}{}", "?:a{ }".repeat(n));
+        assert_eq!(kind(parse_big(chain)), ErrorKind::LimitExceeded);
+        let lists = format!("x = {}{};", "[".repeat(n), "]".repeat(n));
+        assert_eq!(kind(parse_big(lists)), ErrorKind::LimitExceeded);
+        // and right up to the limit is still fine
+        let deepest = format!("x = {}1{};", "(".repeat(299), ")".repeat(299));
+        assert!(parse_big(deepest).is_ok());
+    }
+}
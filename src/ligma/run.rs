@@ -0,0 +1,427 @@
+use crate::ligma::{
+    fun::Fun,
+    vm::Vm,
+    config::InterpreterConfig,
+    lexer::Lexer,
+    include::IncludeOptions,
+    parser::Parser,
+    stmt::Stmt,
+    input::{InputSource, QueueInput},
+    output::{BufferSink, OutputSink},
+    serendipity::LigmaError,
+};
+
+// Which of the two interpreters runs the program. Both mean exactly the same
+// thing by every program, Walk is Fun going over the tree statement by
+// statement, Bytecode compiles it first and is a lot quicker at loops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Walk,
+    Bytecode,
+}
+
+// Everything a whole-program run can be told. The defaults read nothing,
+// keep all the output and never run out of fuel, so
+// RunOptions { fuel: Some(100), ..RunOptions::default() } is the usual way in.
+//
+// Mind the stack with Engine::Walk. Every ligma call is a pile of Rust calls
+// deep, about 10KB of native stack in a release build and 40KB to 100KB in a
+// debug one, depending how many blocks in the call sits, so the default
+// max_call_depth of 300 wants 3MB and up to 30MB of it. A test thread gets
+// 2MB and the main thread usually 8MB. The CLI runs everything on a 256MB
+// thread and the wasm build asks for 16MB (see .cargo/config.toml), anywhere
+// else either do the same or start from InterpreterConfig::for_walker(), like
+// Session does. Bytecode keeps its calls on the heap and gets by on a few
+// dozen KB whatever the depth.
+pub struct RunOptions {
+    pub config: InterpreterConfig,
+    pub fuel: Option<u64>,
+    pub engine: Engine,
+    pub includes: IncludeOptions,
+    pub input: Box<dyn InputSource>,
+    pub output: Box<dyn OutputSink>,
+}
+
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        RunOptions {
+            config: InterpreterConfig::default(),
+            fuel: None,
+            engine: Engine::Bytecode,
+            includes: IncludeOptions::default(),
+            input: Box::new(QueueInput::new(Vec::new())),
+            output: Box::new(BufferSink::new()),
+        }
+    }
+}
+
+// How it went. output is whatever the sink held on to, so it's empty for the
+// streaming ones. errors has every lex diagnostic, or the one parse error, or
+// the one runtime error, render() them against the same source.
+#[derive(Debug)]
+pub struct RunResult {
+    pub output: String,
+    pub errors: Vec<LigmaError>,
+    // false when it never got past lexing and parsing
+    pub ran: bool,
+}
+
+impl RunResult {
+    pub fn ok(&self) -> bool {
+        return self.errors.len() == 0;
+    }
+}
+
+// Lex (includes and all) and parse, nothing runs. A source with bad tokens
+// doesn't get parsed at all, every one of them comes back instead.
+pub fn parse_source(source: &str, includes: &IncludeOptions) -> Result<Vec<Stmt>, Vec<LigmaError>> {
+    return parse_source_with(source, includes, &InterpreterConfig::default());
+}
+
+// parse_source, but nesting as deep as config.max_expr_nesting says and no deeper
+pub fn parse_source_with(source: &str, includes: &IncludeOptions, config: &InterpreterConfig) -> Result<Vec<Stmt>, Vec<LigmaError>> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_includes(includes);
+    let tokens = lexer.lex_all();
+    if lexer.diagnostics().len() > 0 {
+        return Err(lexer.diagnostics().clone());
+    }
+    let mut parser = Parser::new(tokens);
+    parser.set_max_nesting(config.max_expr_nesting);
+    return parser.parse().map_err(|e| vec![e]);
+}
+
+// The one way to run a whole program, the CLI and the wasm exports both end up here.
+pub fn run_source(source: &str, options: RunOptions) -> RunResult {
+    let program = match parse_source_with(source, &options.includes, &options.config) {
+        Ok(program) => program,
+        Err(errors) => {
+            return RunResult {
+                output: options.output.contents(),
+                errors,
+                ran: false,
+            };
+        }
+    };
+    let result: Result<(), LigmaError>;
+    let output: String;
+    match options.engine {
+        Engine::Walk => {
+            let mut fun = Fun::new(program, options.config);
+            fun.set_input(options.input);
+            fun.set_output(options.output);
+            fun.set_fuel(options.fuel);
+            result = fun.eval();
+            output = fun.get_output();
+        }
+        Engine::Bytecode => {
+            let mut vm = Vm::new(program, options.config);
+            vm.set_input(options.input);
+            vm.set_output(options.output);
+            vm.set_fuel(options.fuel);
+            result = vm.eval();
+            output = vm.get_output().contents();
+        }
+    }
+    let errors = match result {
+        Ok(()) => Vec::new(),
+        Err(e) => vec![e],
+    };
+    return RunResult {
+        output,
+        errors,
+        ran: true,
+    };
+}
+
+// How the tests all over the crate run a program: lines for it to read,
+// everything else from options.
+#[cfg(test)]
+pub fn run_with_lines(source: &str, lines: &[&str], options: RunOptions) -> RunResult {
+    return run_source(source, RunOptions {
+        input: Box::new(QueueInput::new(lines.iter().map(|line| line.to_string()).collect())),
+        ..options
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ligma::serendipity::ErrorKind;
+
+    // A bit of everything the language does, each small enough to sweep fuel
+    // over. Some end in an error on purpose, the engines have to agree on
+    // those too.
+    const PROGRAMS: [&str; 12] = [
+        // loops and the else chain
+        "i = 0;
+        $(i < 16){
+            i = i + 1;
+            ?(i % 15 == 0){ !\"fizzbuzz\"; }?:(i % 3 == 0){ !\"fizz\"; }
+            ?:(i % 5 == 0){ !\"buzz\"; }
+            ?{ t = i > 10; ?t{ !\"big\"; } ?{ !i; } }
+            !\" \";
+        }",
+        // the old countdown loop
+        "a = 10; $a{ a = a - 1; b = a % 3; b = b == 0; ?b{ !\"fizz\"; } !a; }",
+        // functions, the argreg and the wrong number of arguments
+        "sq{ @ = _@ * _@; }
+        x = 7; y = sq(x); !y;
+        add(a, b){ @@ a + b; }
+        z = add(2, x * 3); !z;
+        first(n){ i = 0; $(i < 100){ ?(i * i > n){ @@ i; } i = i + 1; } @@ -1; }
+        r = first(50); !r;
+        k = 2; [k]@ = \"two\"; q = [k]@; !q;
+        add(1);",
+        // defs, breaks and [index] variables
+        "#<greet>{ !\"hi\"; }
+        #(greet) #(greet)
+        i = 3; $i{ i = i - 1; !i; z = i == 1; ?z{ $; } }
+        k = \"key\"; [k]arr = 5; v = [k]arr; !v;",
+        // lists, maps, each and a bad index
+        "xs = [1, 2, 3]; push(xs, 4); n = len(xs); !n;
+        last = pop(xs); !last; xs[0] = \"one\"; !xs;
+        m = {\"a\": 1, \"b\": [10, 20]}; m[\"b\"][1] = 99; !m;
+        v = m[\"b\"][1] + xs[2]; !v;
+        $k:(m){ $j:([1, 2]){ ?(j == 2){ $; } !k; !j; } }
+        c = \"abc\"[1]; !c;
+        bad = xs[10];",
+        // unary operators
+        "x = 4; a = -5; b = -x * 2; c = 1 - -2; d = -2.5; e = ~#T#; f = ~0; g = +\"abc\";
+        !a; !b; !c; !d; !e; !f; !g;",
+        // the string operators, quirks and all
+        "a = \"ab\" * 2; b = a + \"cd\"; c = b - 1; d = b / 2; e = b % \"x\"; f = 3 + \"4\";
+        !a; !b; !c; !d; !e; !f;
+        s = \"ab\"; $(len(s) < 40){ s = s + s; } !s;",
+        // reading input until there isn't any
+        "??a; ??b; c = a + b; !c; ??d;",
+        // killing a variable and then using it
+        "a = 1; **a; !\"gone\"; b = a;",
+        // precedence, and going back to the old way
+        "a = 1 + 2 * 3; !a;",
+        "#left_to_right#
+        a = 1 + 2 * 3; !a;",
+        // dividing by zero a call down
+        "f(n){ @@ 100 % n; } i = 2; $(i > -2){ x = f(i); !x; i = i - 1; }",
+    ];
+
+    // same output and the same errors, down to the span and the call stack
+    fn same(source: &str, config: InterpreterConfig, fuel: Option<u64>) -> RunResult {
+        let walk = run_with_lines(source, &["2", "3"], RunOptions {
+            config: config.clone(),
+            fuel,
+            engine: Engine::Walk,
+            ..RunOptions::default()
+        });
+        let bytecode = run_with_lines(source, &["2", "3"], RunOptions {
+            config,
+            fuel,
+            engine: Engine::Bytecode,
+            ..RunOptions::default()
+        });
+        assert_eq!(walk.output, bytecode.output, "output of {:?} with fuel {:?}", source, fuel);
+        assert_eq!(walk.errors, bytecode.errors, "errors of {:?} with fuel {:?}", source, fuel);
+        return bytecode;
+    }
+
+    // both engines, and what they agree on is what it should be
+    fn expect(source: &str, output: &str) {
+        let result = same(source, shallow(), None);
+        assert!(result.ok(), "{:?} went wrong: {:?}", source, result.errors);
+        assert_eq!(result.output, output, "{:?}", source);
+    }
+
+    // the walker needs a lot more native stack than a test thread has to go 300 calls deep
+    fn shallow() -> InterpreterConfig {
+        return InterpreterConfig {
+            max_call_depth: 20,
+            ..InterpreterConfig::default()
+        };
+    }
+
+    fn kind(result: &RunResult) -> ErrorKind {
+        match result.errors.first() {
+            Some(e) => {
+                return e.kind.clone();
+            }
+            None => {
+                panic!("expected an error, got {:?}", result.output);
+            }
+        }
+    }
+
+    #[test]
+    fn engines_agree_on_every_program() {
+        for source in PROGRAMS {
+            let result = same(source, shallow(), None);
+            assert!(result.ran, "{:?} doesn't parse: {:?}", source, result.errors);
+        }
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        expect("a = 1 + 2 * 3; !a; b = (1 + 2) * 3; !b; c = 10 - 4 - 3; !c; d = 2 * 3 + 4 * 5; !d;", "79326");
+        expect("e = 1 + 2 < 4; !e; f = 1 < 2 & 3 < 2 | #T#; !f; g = 20 / 2 / 5; !g; h = 7 % 4 * 2; !h;", "truetrue26");
+    }
+
+    #[test]
+    fn the_pragma_goes_back_to_left_to_right() {
+        expect("#left_to_right#\na = 1 + 2 * 3; !a; b = 2 * 3 + 4 * 5; !b; #precedence# c = 1 + 2 * 3; !c;", "9507");
+        // a def is parsed on its own, but in the mode it was written in
+        expect("#left_to_right#\n#<d>{ x = 1 + 2 * 3; !x; }\n#precedence#\n#(d) y = 1 + 2 * 3; !y;", "97");
+    }
+
+    #[test]
+    fn unary_operators_do_what_they_say() {
+        expect("x = 4; a = -5; b = -x * 2; c = 1 - -2; d = -2.5; !a; !b; !c; !d;", "-5-83-2.5");
+        expect("a = -2 * -3; b = ~(1 < 2); c = -(1 + 2) * 2; x = 3; d = 2 - -x; !a; !b; !c; !d;", "6false-65");
+        // ~ uses the same truthiness as ?, and + and - turn anything but a float into an int
+        expect("e = ~#T#; f = ~0; g = ~\"\"; h = ~~3; i = +\"abc\"; j = --3; !e; !f; !g; !h; !i; !j;", "falsetruetruetrue33");
+    }
+
+    #[test]
+    fn conditions_are_worked_out_every_time_round() {
+        expect("i = 0; $(i < 5){ ?(i % 2 == 0){ !i; } i = i + 1; }", "024");
+        expect("n = 0; $(n * n < 20){ n = n + 1; } !n; ?(n == 5 & #T#){ !\"five\"; } ?(n){ !\"truthy\"; } ?(n - 5){ !\"zero\"; }", "5fivetruthy");
+        expect("$(#F#){ !\"never\"; } ?(1 > 2){ !\"no\"; } a = 3; $(a){ a = a - 1; !a; }", "210");
+    }
+
+    #[test]
+    fn only_one_branch_of_an_else_chain_runs() {
+        expect("x = 5; ?(x > 3){ !\"big\"; }?{ !\"small\"; } ?(x > 9){ !\"huge\"; }?{ !\"not huge\"; }", "bignot huge");
+        expect("i = 0; $(i < 4){ ?(i == 0){ !\"a\"; }?:(i == 1){ !\"b\"; }?:(i == 2){ !\"c\"; }?{ !\"d\"; } i = i + 1; }", "abcd");
+        expect("t = #F#; ?t{ !\"yes\"; }?{ !\"no\"; } u = 1; ?t{ !\"a\"; }?:u{ !\"b\"; }?{ !\"c\"; }", "nob");
+        // the else belongs to the ? it follows, not to one inside its block
+        expect("x = 1; ?(x == 1){ ?(x > 5){ !\"in\"; }?{ !\"out\"; } }?{ !\"no\"; } ?(x == 2){ !\"two\"; }?:(x == 3){ !\"three\"; } !\"end\";", "outend");
+    }
+
+    #[test]
+    fn functions_take_what_they_name_and_give_back_what_they_return() {
+        expect("add(a, b){ @@ a + b; } x = 2; z = add(x, x * 3); !z; w = add(\"a\", 1); !w; y = add(add(1, 2), 3); !y;", "81a6");
+        expect("f(n){ ?(n < 2){ @@ n; } a = f(n - 1); b = f(n - 2); @@ a + b; } r = f(10); !r;", "55");
+        // @@ leaves straight away, even from inside a loop
+        expect("first(n){ i = 0; $(i < 100){ ?(i * i > n){ @@ i; } i = i + 1; } @@ -1; } r = first(50); !r;", "8");
+        // parameters are the function's own, the caller's a is left alone
+        expect("a = 1; g(a){ a = a + 10; @@ a; } b = g(5); !b; !a;", "151");
+        // and the old way still works
+        expect("sq{ @ = _@ * _@; } x = 7; y = sq(x); !y; z = sq(3); !z; k = 2; [k]@ = \"two\"; q = [k]@; !q;", "499two");
+        let result = same("add(a, b){ @@ a + b; } add(1);", shallow(), None);
+        assert_eq!(kind(&result), ErrorKind::ArgumentCount);
+        let result = same("noret(a){ b = a; } x = noret(1);", shallow(), None);
+        assert_eq!(kind(&result), ErrorKind::UndefinedArgreg);
+    }
+
+    #[test]
+    fn lists_and_maps_print_compare_and_iterate() {
+        expect("xs = [1, 2, 3]; push(xs, 4); n = len(xs); !n; last = pop(xs); !last; !xs; e = []; !e;", "44[1, 2, 3][]");
+        // maps print in key order, strings inside get their quotes
+        expect("m = {\"b\": 2, \"a\": [1, \"x\"]}; !m; v = m[\"a\"][1]; !v; m[\"c\"] = {}; !m;", "{\"a\": [1, \"x\"], \"b\": 2}x{\"a\": [1, \"x\"], \"b\": 2, \"c\": {}}");
+        expect("a = [1, [2]]; b = [1, [2]]; c = a == b; !c; d = a == [1, 2]; !d; m = {\"k\": 1}; n = {\"k\": 1}; e = m == n; !e;", "truefalsetrue");
+        expect("s = 0; $x:([1, 2, 3]){ s = s + x; } !s; $k:({\"b\": 1, \"a\": 2}){ !k; }", "6ab");
+        // they're values, so handing one to a function or another name copies it
+        expect("f(l){ push(l, 9); @@ len(l); } xs = [1]; n = f(xs); !n; !xs; ys = xs; push(ys, 2); !xs; !ys;", "2[1][1][1, 2]");
+    }
+
+    #[test]
+    fn engines_run_out_of_fuel_at_the_same_step() {
+        for source in PROGRAMS {
+            for fuel in 0..120 {
+                same(source, shallow(), Some(fuel));
+            }
+        }
+    }
+
+    #[test]
+    fn engines_agree_under_limits() {
+        let configs = [
+            InterpreterConfig { max_variables: Some(4), ..shallow() },
+            InterpreterConfig { max_call_depth: 3, ..shallow() },
+            InterpreterConfig { max_expr_nesting: 3, ..shallow() },
+            InterpreterConfig { max_string_length: Some(12), ..shallow() },
+            InterpreterConfig { max_output_bytes: Some(15), ..shallow() },
+        ];
+        for config in configs {
+            for source in PROGRAMS {
+                same(source, config.clone(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn engines_agree_on_runtime_errors() {
+        let cases = [
+            ("x = y + 1;", ErrorKind::UndefinedVariable),
+            ("x = nope(1);", ErrorKind::UndefinedFunction),
+            ("r(n){ @@ r(n + 1); } x = r(0);", ErrorKind::LimitExceeded),
+            ("i = 0; $(#T#){ i = i + 1; }", ErrorKind::OutOfFuel),
+            ("a = 1 / 0;", ErrorKind::Arithmetic),
+            ("a = 7 % 0;", ErrorKind::Arithmetic),
+            ("a = -9223372036854775807 - 1; b = a / -1;", ErrorKind::Arithmetic),
+            ("a = 9223372036854775807 * 2;", ErrorKind::Arithmetic),
+            ("a = 9223372036854775807; b = a + 1;", ErrorKind::Arithmetic),
+            ("a = -9223372036854775807; b = a - 2;", ErrorKind::Arithmetic),
+            ("a = -9223372036854775807 - 1; b = -a;", ErrorKind::Arithmetic),
+            ("#left_to_right#\na = 9223372036854775807 + 1 * 1;", ErrorKind::Arithmetic),
+            ("q = 5; push(q, 1);", ErrorKind::TypeMismatch),
+            ("a = []; x = pop(a);", ErrorKind::BadIndex),
+            ("a = \"ab\" * 3000000000000;", ErrorKind::LimitExceeded),
+        ];
+        for (source, expected) in cases {
+            let result = same(source, shallow(), Some(1000));
+            assert_eq!(kind(&result), expected, "{}", source);
+            assert!(result.errors[0].span.is_some(), "{} has no span", source);
+        }
+    }
+
+    #[test]
+    fn ints_go_right_up_to_the_edge() {
+        expect("a = 9223372036854775806 + 1; !a; b = -9223372036854775807 - 1; !b; c = -4611686018427387904 * 2; !c;", "9223372036854775807-9223372036854775808-9223372036854775808");
+    }
+
+    #[test]
+    fn strings_are_counted_in_chars() {
+        let source = "s = \"h\u{e9}llo\"; n = len(s); !n; i = 0; $(i < len(s)){ c = s[i]; !c; i = i + 1; }";
+        let result = same(source, shallow(), None);
+        assert!(result.ok(), "{:?}", result.errors);
+        assert_eq!(result.output, "5h\u{e9}llo");
+    }
+
+    #[test]
+    fn output_indexes_like_everything_else() {
+        let source = "xs = [5, 6]; !xs[0]; i = 1; !xs[i]; m = {\"a\": [1, 2]}; !m[\"a\"][1]; [i]xs = \"slot\"; ![i]xs;";
+        let result = same(source, shallow(), None);
+        assert!(result.ok(), "{:?}", result.errors);
+        assert_eq!(result.output, "562slot");
+    }
+
+    #[test]
+    fn assigning_into_a_list_changes_it_where_it_is() {
+        let source = "k = \"x\"; [k]ys = [1, [2, 3]]; [k]ys[1][0] = \"deep\"; v = [k]ys; !v;
+        m = {}; m[\"a\"] = 1; m[\"a\"] = 2; !m;
+        a = [1, 2]; a[0] = pop(a); !a;";
+        let result = same(source, shallow(), None);
+        assert!(result.ok(), "{:?}", result.errors);
+        assert_eq!(result.output, "[1, [\"deep\", 3]]{\"a\": 2}[2]");
+        for source in ["q[0] = 1;", "q = 1; q[0] = 2;", "q = [1]; q[1] = 2;", "q = {}; q[\"a\"][\"b\"] = 1;"] {
+            let result = same(source, shallow(), None);
+            assert!(!result.ok(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn strings_have_a_limit_by_default() {
+        for engine in [Engine::Walk, Engine::Bytecode] {
+            for source in ["a = \"ab\" * 3000000000000;", "s = \"ab\"; $(#T#){ s = s + s; }"] {
+                let result = run_source(source, RunOptions { engine, ..RunOptions::default() });
+                assert_eq!(kind(&result), ErrorKind::LimitExceeded, "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn running_out_of_calls_keeps_the_stack_in_both() {
+        let result = same("r(n){ @@ r(n + 1); } x = r(0);", shallow(), None);
+        // the top level is one of the 20, and the call that didn't happen isn't on it
+        assert_eq!(result.errors[0].call_stack.len(), 18);
+    }
+}
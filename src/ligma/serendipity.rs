@@ -0,0 +1,198 @@
+use std::fmt;
+
+// Every happy little accident the interpreter runs into ends up here.
+// Fun used to just shrug and keep going, now it tells you what went wrong
+// and where.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UndefinedVariable,
+    UndefinedFunction,
+    UndefinedArgreg,
+    UndefinedDef,
+    UnexpectedToken,
+    // the input stopped halfway through a string, comment or expression
+    UnexpectedEof,
+    IllegalToken,
+    // a #[path] that isn't there, can't be read or goes round in a circle
+    Include,
+    LimitExceeded,
+    ArgumentCount,
+    BadIndex,
+    TypeMismatch,
+    // dividing by zero, or an int that doesn't fit in 64 bits
+    Arithmetic,
+    NoInput,
+    OutOfFuel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    // None for the file you handed the lexer, Some(path) for #[path] includes
+    pub file: Option<String>,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// how many frames of a long call stack get shown at either end
+const SHOWN_FRAMES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LigmaError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub call_stack: Vec<String>,
+}
+
+impl LigmaError {
+    pub fn new(kind: ErrorKind, message: String) -> LigmaError {
+        LigmaError {
+            kind,
+            message,
+            span: None,
+            call_stack: Vec::new(),
+        }
+    }
+
+    pub fn undefined_variable(name: &str) -> LigmaError {
+        LigmaError::new(ErrorKind::UndefinedVariable, format!("undefined variable `{}`", name))
+    }
+
+    pub fn undefined_function(name: &str) -> LigmaError {
+        LigmaError::new(ErrorKind::UndefinedFunction, format!("undefined function `{}`", name))
+    }
+
+    pub fn undefined_argreg(index: &Option<String>) -> LigmaError {
+        let msg = match index {
+            Some(i) => format!("argreg slot `[{}]@` was never set", i),
+            None => String::from("argreg `@` was never set"),
+        };
+        LigmaError::new(ErrorKind::UndefinedArgreg, msg)
+    }
+
+    pub fn unexpected(msg: &str) -> LigmaError {
+        LigmaError::new(ErrorKind::UnexpectedToken, msg.to_string())
+    }
+
+    // only the innermost site knows where it happened, so don't clobber it
+    pub fn with_span(mut self, span: Span) -> LigmaError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    // called on the way back out of call_func, so the innermost frame is first
+    pub fn push_frame(mut self, frame: String) -> LigmaError {
+        self.call_stack.push(frame);
+        self
+    }
+}
+
+impl LigmaError {
+    // Pretty version of Display with the offending line and a row of carets
+    // under the bad bit. source has to be the text the span points into, so
+    // errors from #[included] files only get the header.
+    pub fn render(&self, source: &str) -> String {
+        let mut rendered = format!("error: {}\n", self);
+        let span = match &self.span {
+            Some(span) => span,
+            None => {
+                return rendered;
+            }
+        };
+        if span.file.is_some() || span.start > source.len() {
+            return rendered;
+        }
+        let line_start = match source[..span.start].rfind('\n') {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        let line_end = match source[span.start..].find('\n') {
+            Some(i) => span.start + i,
+            None => source.len(),
+        };
+        let text = &source[line_start..line_end];
+        // anything running past the end of the line (unterminated strings
+        // and comments) just gets underlined to the end of it
+        let end = span.end.min(line_end);
+        let width = source[span.start..end].trim_end().chars().count().max(1);
+        let pad = source[line_start..span.start].chars().count();
+        let gutter = " ".repeat(span.line.to_string().len());
+
+        rendered.push_str(&format!("{} |\n", gutter));
+        rendered.push_str(&format!("{} | {}\n", span.line, text));
+        rendered.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(pad), "^".repeat(width)));
+        return rendered;
+    }
+}
+
+impl fmt::Display for LigmaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(span) = &self.span {
+            match span.file {
+                Some(_) => write!(f, " at {}", span)?,
+                None => write!(f, " at line {}", span)?,
+            }
+        }
+        // running out of call depth would otherwise list hundreds of the same frame
+        let frames = &self.call_stack;
+        if frames.len() <= SHOWN_FRAMES * 2 + 1 {
+            for frame in frames {
+                write!(f, "\n    in {}", frame)?;
+            }
+            return Ok(());
+        }
+        for frame in &frames[..SHOWN_FRAMES] {
+            write!(f, "\n    in {}", frame)?;
+        }
+        write!(f, "\n    ... {} more", frames.len() - SHOWN_FRAMES * 2)?;
+        for frame in &frames[frames.len() - SHOWN_FRAMES..] {
+            write!(f, "\n    in {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LigmaError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_call_stacks_show_both_ends() {
+        let mut e = LigmaError::new(ErrorKind::LimitExceeded, String::from("too deep"));
+        for depth in 0..300 {
+            e = e.push_frame(format!("function `r` called at line {}", depth));
+        }
+        let shown = e.to_string();
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(lines.len(), 1 + SHOWN_FRAMES * 2 + 1);
+        assert_eq!(lines[1], "    in function `r` called at line 0");
+        assert_eq!(lines[SHOWN_FRAMES + 1], "    ... 290 more");
+        assert_eq!(lines[lines.len() - 1], "    in function `r` called at line 299");
+    }
+
+    #[test]
+    fn short_call_stacks_show_every_frame() {
+        let mut e = LigmaError::new(ErrorKind::LimitExceeded, String::from("too deep"));
+        for depth in 0..11 {
+            e = e.push_frame(format!("function `r` called at line {}", depth));
+        }
+        assert_eq!(e.to_string().lines().count(), 12);
+        assert!(!e.to_string().contains("more"));
+    }
+}
@@ -0,0 +1,112 @@
+// Fun only ever runs on one thread, so what its frames share is an
+// Rc<RefCell<_>> and getting at it is a flag check. With the `sync` feature
+// it's an Arc<Mutex<_>> instead and every input and output has to be Send,
+// which makes the whole of Fun Send for anyone who wants to move one between
+// threads. Either way get() hands out the thing itself for as long as the
+// guard lives, so don't hold on to it across anything that might want it too.
+
+#[cfg(not(feature = "sync"))]
+mod imp {
+    use std::cell::{RefCell, RefMut};
+    use std::rc::Rc;
+
+    pub type Guard<'a, T> = RefMut<'a, T>;
+
+    #[derive(Debug, Default)]
+    pub struct Shared<T>(Rc<RefCell<T>>);
+
+    impl<T> Shared<T> {
+        pub fn new(value: T) -> Shared<T> {
+            return Shared(Rc::new(RefCell::new(value)));
+        }
+
+        pub fn get(&self) -> Guard<'_, T> {
+            return self.0.borrow_mut();
+        }
+    }
+
+    impl<T> Clone for Shared<T> {
+        fn clone(&self) -> Shared<T> {
+            return Shared(Rc::clone(&self.0));
+        }
+    }
+
+    // anything goes when nothing leaves the thread
+    pub trait MaybeSend {}
+
+    impl<T: ?Sized> MaybeSend for T {}
+}
+
+#[cfg(feature = "sync")]
+mod imp {
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    pub type Guard<'a, T> = MutexGuard<'a, T>;
+
+    #[derive(Debug, Default)]
+    pub struct Shared<T>(Arc<Mutex<T>>);
+
+    impl<T> Shared<T> {
+        pub fn new(value: T) -> Shared<T> {
+            return Shared(Arc::new(Mutex::new(value)));
+        }
+
+        // a panic halfway through a program doesn't make what it left behind
+        // any less readable, so a poisoned lock is just a lock
+        pub fn get(&self) -> Guard<'_, T> {
+            match self.0.lock() {
+                Ok(guard) => {
+                    return guard;
+                }
+                Err(poisoned) => {
+                    return poisoned.into_inner();
+                }
+            }
+        }
+    }
+
+    impl<T> Clone for Shared<T> {
+        fn clone(&self) -> Shared<T> {
+            return Shared(Arc::clone(&self.0));
+        }
+    }
+
+    pub trait MaybeSend: Send {}
+
+    impl<T: ?Sized + Send> MaybeSend for T {}
+}
+
+pub use imp::{Guard, MaybeSend, Shared};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_one_value() {
+        let shared = Shared::new(vec![1]);
+        let other = shared.clone();
+        other.get().push(2);
+        assert_eq!(*shared.get(), vec![1, 2]);
+    }
+
+    // only builds with --features sync, which is most of the point
+    #[cfg(feature = "sync")]
+    #[test]
+    fn a_fun_can_finish_on_another_thread() {
+        use crate::ligma::config::InterpreterConfig;
+        use crate::ligma::fun::Fun;
+        use crate::ligma::include::IncludeOptions;
+        use crate::ligma::output::BufferSink;
+        use crate::ligma::run::parse_source;
+
+        let program = parse_source("f(n){ @@ n * 2; } x = f(21); !x;", &IncludeOptions::default()).unwrap();
+        let mut fun = Fun::new(program, InterpreterConfig::default());
+        fun.set_output(Box::new(BufferSink::new()));
+        let output = std::thread::spawn(move || {
+            fun.eval().unwrap();
+            return fun.get_output();
+        });
+        assert_eq!(output.join().unwrap(), "42");
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use crate::Expr;
+use crate::ligma::serendipity::{LigmaError, Span};
+
+// What the parser turns a token stream into. Expressions reuse Expr, so
+// anything that is "a value or something that makes a value" lives over there.
+// Every statement keeps the span of the token that started it.
+// What a #<name>{ } def parsed to. Defs get parsed once along with the rest
+// of the program and every #(name) shares the result. A def that doesn't
+// parse only blows up once somebody inserts it, same as it always did.
+pub type DefBody = Arc<Result<Vec<Stmt>, LigmaError>>;
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Assign(Option<Expr>, String, Expr, Span), // [index]name = value;
+    Argreg(Option<Expr>, Expr, Span), // [index]@ = value;
+    SetIndex(Option<Expr>, String, Vec<Expr>, Expr, Span), // name[key][key] = value;
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>, Span), // ?cond{ ... } ?{ ... }, ?: chains nest in the else
+    Loop(Expr, Vec<Stmt>, Span), // $cond{ ... }
+    Each(String, Expr, Vec<Stmt>, Span), // $item:(collection){ ... }
+    Break(Span), // $;
+    Output(Expr, Span), // !name;
+    OutputStr(Vec<String>, Span), // !"text";
+    Input(Option<Expr>, String, Span), // ??name;
+    Kill(String, Span), // **name;
+    Function(String, Vec<String>, Vec<Stmt>, Span), // name{ ... } or name(a, b){ ... }
+    Call(String, Vec<Expr>, Span), // name(arg, arg)
+    Return(Option<Expr>, Span), // @@ value;
+    Def(String, DefBody, Span), // #<name>{ ... }
+    Insert(String, Span), // #(name)
+}
+
+impl Stmt {
+    pub fn span(&self) -> &Span {
+        match self {
+            Stmt::Assign(_, _, _, span) => span,
+            Stmt::Argreg(_, _, span) => span,
+            Stmt::SetIndex(_, _, _, _, span) => span,
+            Stmt::If(_, _, _, span) => span,
+            Stmt::Loop(_, _, span) => span,
+            Stmt::Each(_, _, _, span) => span,
+            Stmt::Break(span) => span,
+            Stmt::Output(_, span) => span,
+            Stmt::OutputStr(_, span) => span,
+            Stmt::Input(_, _, span) => span,
+            Stmt::Kill(_, span) => span,
+            Stmt::Function(_, _, _, span) => span,
+            Stmt::Call(_, _, span) => span,
+            Stmt::Return(_, span) => span,
+            Stmt::Def(_, _, span) => span,
+            Stmt::Insert(_, span) => span,
+        }
+    }
+}
@@ -0,0 +1,889 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::vec;
+use crate::Expr;
+use crate::Token;
+use crate::ligma::compile::{Chunk, Compiler, Op, Operand, Program};
+use crate::ligma::config::InterpreterConfig;
+use crate::ligma::fun::Fun;
+use crate::ligma::input::{InputSource, StdinInput};
+use crate::ligma::output::{BufferSink, OutputSink};
+use crate::ligma::serendipity::{ErrorKind, LigmaError};
+use crate::ligma::stmt::{DefBody, Stmt};
+
+// Runs what compile.rs makes of a program. It's meant to behave exactly like
+// Fun::eval, same output, same errors pointing at the same places, same fuel
+// burnt, only without walking the tree and cloning a whole Fun for every call.
+// Calls don't recurse on the real stack either, a frame is just a Vec entry.
+
+// a variable, the plain value and all its [index] slots
+#[derive(Debug, Clone, Default)]
+struct Var {
+    plain: Option<Expr>,
+    indexed: HashMap<String, Expr>,
+}
+
+impl Var {
+    fn get(&self, index: &Option<String>) -> Option<&Expr> {
+        match index {
+            Some(i) => self.indexed.get(i),
+            None => self.plain.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, index: &Option<String>) -> Option<&mut Expr> {
+        match index {
+            Some(i) => self.indexed.get_mut(i),
+            None => self.plain.as_mut(),
+        }
+    }
+
+    fn insert(&mut self, index: Option<String>, value: Expr) {
+        match index {
+            Some(i) => {
+                self.indexed.insert(i, value);
+            }
+            None => {
+                self.plain = Some(value);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum FrameKind {
+    Main,
+    // the call site in the frame below, and whether it wants a value back
+    Call(usize, bool),
+    // #(def) code borrowing the vars of the frame below, and where a $; in it goes there
+    Insert(Option<usize>),
+}
+
+#[derive(Debug)]
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    vars: Vec<Option<Var>>,
+    // how many of vars are set, for max_variables
+    live: usize,
+    // the items every $name:(…) we're inside has left
+    items: Vec<vec::IntoIter<Expr>>,
+    kind: FrameKind,
+    // names #(def) code added to this frame, their slots come after the chunk's own
+    extra: Vec<String>,
+    // how high the value stack was when the frame started
+    base: usize,
+}
+
+impl Frame {
+    fn new(chunk: Rc<Chunk>, kind: FrameKind, base: usize) -> Frame {
+        let vars = vec![None; chunk.names.len()];
+        Frame {
+            chunk,
+            ip: 0,
+            vars,
+            live: 0,
+            items: Vec::new(),
+            kind,
+            extra: Vec::new(),
+            base,
+        }
+    }
+
+    fn name(&self, slot: usize) -> &str {
+        return &self.chunk.names[slot];
+    }
+}
+
+#[derive(Debug)]
+pub struct Vm {
+    functions: Vec<Rc<Chunk>>,
+    // what each function name means right now, into functions
+    declared: HashMap<String, usize>,
+    defs: HashMap<String, DefBody>,
+    // what a def compiled to, by which def, the chunk it got inserted into and
+    // the names that chunk picked up from earlier inserts. Every def and chunk
+    // in here lives as long as the Vm, so the addresses can't get reused.
+    expanded: HashMap<(usize, usize, Vec<String>), Rc<Chunk>>,
+    argreg: HashMap<Option<String>, Expr>,
+    frames: Vec<Frame>,
+    stack: Vec<Expr>,
+    // arguments evaluated so far and the functions they're for
+    args: Vec<Var>,
+    callees: Vec<usize>,
+    // the top level counts as one, like Fun's stack
+    depth: usize,
+    fuel: Option<u64>,
+    output: Box<dyn OutputSink>,
+    written: usize,
+    input: Box<dyn InputSource>,
+    config: InterpreterConfig,
+}
+
+impl Vm {
+    pub fn new(body: Vec<Stmt>, config: InterpreterConfig) -> Vm {
+        let program = Compiler::new(&config, 0).compile(&body);
+        return Vm::from_program(program, config);
+    }
+
+    pub fn from_program(program: Program, config: InterpreterConfig) -> Vm {
+        let main = Rc::new(program.main);
+        Vm {
+            functions: program.functions.into_iter().map(Rc::new).collect(),
+            declared: HashMap::new(),
+            defs: HashMap::new(),
+            expanded: HashMap::new(),
+            argreg: HashMap::new(),
+            frames: vec![Frame::new(main, FrameKind::Main, 0)],
+            stack: Vec::new(),
+            args: Vec::new(),
+            callees: Vec::new(),
+            depth: 1,
+            fuel: None,
+            output: Box::new(BufferSink::new()),
+            written: 0,
+            input: Box::new(StdinInput),
+            config,
+        }
+    }
+
+    pub fn set_fuel(&mut self, steps: Option<u64>) {
+        self.fuel = steps;
+    }
+
+    pub fn set_input(&mut self, source: Box<dyn InputSource>) {
+        self.input = source;
+    }
+
+    pub fn set_output(&mut self, sink: Box<dyn OutputSink>) {
+        self.output = sink;
+    }
+
+    pub fn get_output(&self) -> &dyn OutputSink {
+        return self.output.as_ref();
+    }
+
+    pub fn eval(&mut self) -> Result<(), LigmaError> {
+        match self.execute() {
+            Ok(()) => {
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(self.unwind(e));
+            }
+        }
+    }
+
+    // gives the error the span of the statement it happened in, then a frame
+    // for every call it came back out of, the way eval_block and call_func do
+    fn unwind(&mut self, e: LigmaError) -> LigmaError {
+        let mut e = e;
+        if let Some(frame) = self.frames.last() {
+            let at = frame.ip.saturating_sub(1);
+            if let Some(line) = frame.chunk.lines.get(at) {
+                e = e.with_span(frame.chunk.spans[*line].clone());
+            }
+        }
+        while let Some(frame) = self.frames.pop() {
+            if let (FrameKind::Call(site, _), Some(caller)) = (&frame.kind, self.frames.last()) {
+                let site = &caller.chunk.calls[*site];
+                let call = match site.span.file {
+                    Some(_) => format!("function `{}` called at {}", site.name, site.span),
+                    None => format!("function `{}` called at line {}", site.name, site.span),
+                };
+                e = e.push_frame(call);
+            }
+        }
+        return e;
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        return self.frames.last_mut().unwrap();
+    }
+
+    fn pop(&mut self) -> Expr {
+        return self.stack.pop().unwrap_or(Expr::New);
+    }
+
+    fn pop_index(&mut self) -> Option<String> {
+        let index = self.pop();
+        return Some(Expr::parse_exp_string(index));
+    }
+
+    // running out on the last of several steps is the same as running out on the first
+    fn burn(&mut self, steps: u64) -> Result<(), LigmaError> {
+        match self.fuel {
+            Some(left) if left < steps => {
+                return Err(LigmaError::new(ErrorKind::OutOfFuel, String::from("out of fuel, the program ran longer than its step budget")));
+            }
+            Some(left) => {
+                self.fuel = Some(left - steps);
+            }
+            None => {}
+        }
+        return Ok(());
+    }
+
+    fn check_string(&self, value: &Expr) -> Result<(), LigmaError> {
+        if let Expr::String(t) = value {
+            return Fun::check_length(t.len() as u128, self.config.max_string_length);
+        }
+        return Ok(());
+    }
+
+    fn push_checked(&mut self, value: Expr) -> Result<(), LigmaError> {
+        self.check_string(&value)?;
+        self.stack.push(value);
+        return Ok(());
+    }
+
+    fn print(&mut self, to_print: &str) -> Result<(), LigmaError> {
+        if let Some(max) = self.config.max_output_bytes {
+            if self.written + to_print.len() > max {
+                let msg = format!("output would go past the {} bytes allowed", max);
+                return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            }
+            self.written += to_print.len();
+        }
+        self.output.write(to_print);
+        return Ok(());
+    }
+
+    fn get_var(&mut self, slot: usize, index: &Option<String>) -> Result<Expr, LigmaError> {
+        return self.var_ref(slot, index).cloned();
+    }
+
+    fn var_ref(&mut self, slot: usize, index: &Option<String>) -> Result<&Expr, LigmaError> {
+        let frame = self.frames.last().unwrap();
+        if let Some(Some(var)) = frame.vars.get(slot) {
+            if let Some(value) = var.get(index) {
+                return Ok(value);
+            }
+        }
+        return Err(Vm::missing(&frame.chunk, slot, index));
+    }
+
+    fn missing(chunk: &Chunk, slot: usize, index: &Option<String>) -> LigmaError {
+        match index {
+            Some(i) => {
+                return LigmaError::undefined_variable(&format!("[{}]{}", i, chunk.names[slot]));
+            }
+            None => {
+                return LigmaError::undefined_variable(&chunk.names[slot]);
+            }
+        }
+    }
+
+    fn var_mut(&mut self, slot: usize, index: &Option<String>) -> Result<&mut Expr, LigmaError> {
+        let frame = self.frames.last_mut().unwrap();
+        let chunk = &frame.chunk;
+        match frame.vars[slot].as_mut().and_then(|var| var.get_mut(index)) {
+            Some(var) => {
+                return Ok(var);
+            }
+            None => {
+                return Err(Vm::missing(chunk, slot, index));
+            }
+        }
+    }
+
+    // the list itself for push and pop to change, not a copy of it
+    fn list_mut(&mut self, slot: usize, index: &Option<String>, site: usize) -> Result<&mut Vec<Expr>, LigmaError> {
+        let frame = self.frames.last_mut().unwrap();
+        let chunk = &frame.chunk;
+        let var = match frame.vars[slot].as_mut().and_then(|var| var.get_mut(index)) {
+            Some(var) => var,
+            None => {
+                return Err(Vm::missing(chunk, slot, index));
+            }
+        };
+        match var {
+            Expr::List(items) => {
+                return Ok(items);
+            }
+            _ => {
+                let msg = format!("`{}` needs a list, `{}` isn't one", chunk.calls[site].name, chunk.names[slot]);
+                return Err(LigmaError::new(ErrorKind::TypeMismatch, msg));
+            }
+        }
+    }
+
+    fn set_var(&mut self, slot: usize, index: Option<String>, value: Expr) -> Result<(), LigmaError> {
+        let max = self.config.max_variables;
+        let frame = self.frame();
+        if let Some(var) = &mut frame.vars[slot] {
+            var.insert(index, value);
+            return Ok(());
+        }
+        if let Some(max) = max {
+            if frame.live >= max {
+                let msg = format!("`{}` would be one variable more than the {} allowed", frame.name(slot), max);
+                return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+            }
+        }
+        let mut var = Var::default();
+        var.insert(index, value);
+        frame.vars[slot] = Some(var);
+        frame.live += 1;
+        return Ok(());
+    }
+
+    fn execute(&mut self) -> Result<(), LigmaError> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            if frame.ip >= frame.chunk.code.len() {
+                if self.finish(None)? {
+                    return Ok(());
+                }
+                continue;
+            }
+            let op = frame.chunk.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                Op::Burn(steps) => {
+                    self.burn(steps as u64)?;
+                }
+                Op::Const(i) => {
+                    let value = frame.chunk.consts[i].clone();
+                    self.push_checked(value)?;
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let top = self.stack.last().cloned().unwrap_or(Expr::New);
+                    self.stack.push(top);
+                }
+                Op::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Op::Load(slot, span) => {
+                    if let Some(Some(Var { plain: Some(value), .. })) = frame.vars.get(slot) {
+                        self.stack.push(value.clone());
+                        continue;
+                    }
+                    let value = self.load(slot, None, span)?;
+                    self.stack.push(value);
+                }
+                Op::LoadIndexed(slot, span) => {
+                    let index = self.pop_index();
+                    let value = self.load(slot, index, span)?;
+                    self.stack.push(value);
+                }
+                Op::Store(slot) => {
+                    let value = self.stack.pop().unwrap_or(Expr::New);
+                    if let Some(Some(var)) = frame.vars.get_mut(slot) {
+                        var.plain = Some(value);
+                        continue;
+                    }
+                    self.set_var(slot, None, value)?;
+                }
+                Op::StoreIndexed(slot) => {
+                    let value = self.pop();
+                    let index = self.pop_index();
+                    self.set_var(slot, index, value)?;
+                }
+                Op::Kill(slot) => {
+                    if frame.vars[slot].take().is_some() {
+                        frame.live -= 1;
+                    }
+                }
+                Op::LoadArgreg(span) => {
+                    let value = self.argreg_slot(None, span)?;
+                    self.stack.push(value);
+                }
+                Op::LoadArgregIndexed(span) => {
+                    let index = self.pop_index();
+                    let value = self.argreg_slot(index, span)?;
+                    self.stack.push(value);
+                }
+                Op::StoreArgreg => {
+                    let value = self.pop();
+                    self.argreg.insert(None, value);
+                }
+                Op::StoreArgregIndexed => {
+                    let value = self.pop();
+                    let index = self.pop_index();
+                    self.argreg.insert(index, value);
+                }
+                Op::BinOp(i, left, right) => {
+                    let chunk = Rc::clone(&frame.chunk);
+                    let (op, span) = &chunk.operators[i as usize];
+                    if let Some(value) = self.int_binop(&chunk, op, left, right) {
+                        let value = value.map_err(|e| e.with_span(chunk.spans[*span].clone()))?;
+                        self.stack.push(value);
+                        continue;
+                    }
+                    let (exp_a, exp_b) = match (left, right) {
+                        (Operand::Stack, Operand::Stack) => {
+                            let exp_b = self.pop();
+                            (self.pop(), exp_b)
+                        }
+                        (Operand::Stack, right) => {
+                            let exp_b = self.operand(&chunk, right)?;
+                            (self.pop(), exp_b)
+                        }
+                        (left, right) => {
+                            let exp_a = self.operand(&chunk, left)?;
+                            (exp_a, self.operand(&chunk, right)?)
+                        }
+                    };
+                    if let Some(len) = Fun::string_len(&exp_a, op, &exp_b) {
+                        Fun::check_length(len, self.config.max_string_length).map_err(|e| e.with_span(chunk.spans[*span].clone()))?;
+                    }
+                    let steps = Fun::extra_steps(&exp_a, op, &exp_b);
+                    if steps > 0 {
+                        self.burn(steps)?;
+                    }
+                    let value = Fun::binop(exp_a, op, exp_b).map_err(|e| e.with_span(chunk.spans[*span].clone()))?;
+                    self.push_checked(value)?;
+                }
+                Op::UnOp(i) => {
+                    let op = frame.chunk.tokens[i].clone();
+                    let exp = self.pop();
+                    self.stack.push(Fun::eval_unop(&op, exp)?);
+                }
+                Op::List(len) => {
+                    let items = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(Expr::List(items));
+                }
+                Op::Map(len) => {
+                    let entries = self.stack.split_off(self.stack.len() - len * 2);
+                    let mut values: BTreeMap<String, Expr> = BTreeMap::new();
+                    let mut entries = entries.into_iter();
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        values.insert(Expr::parse_exp_string(key), value);
+                    }
+                    self.stack.push(Expr::Map(values));
+                }
+                Op::Index(span) => {
+                    let span = frame.chunk.spans[span].clone();
+                    let key = self.pop();
+                    let target = self.pop();
+                    let value = Fun::get_index(&target, key).map_err(|e| e.with_span(span))?;
+                    self.push_checked(value)?;
+                }
+                Op::Need(slot, span) => {
+                    if let Err(e) = self.var_ref(slot, &None) {
+                        return Err(e.with_span(self.frame().chunk.spans[span].clone()));
+                    }
+                }
+                Op::IndexSlot(slot, span) => {
+                    let span = frame.chunk.spans[span].clone();
+                    let key = self.pop();
+                    let value = Fun::get_index(self.var_ref(slot, &None)?, key).map_err(|e| e.with_span(span))?;
+                    self.push_checked(value)?;
+                }
+                Op::Have(slot, indexed) => {
+                    let index = match indexed {
+                        true => self.stack.last().cloned().map(Expr::parse_exp_string),
+                        false => None,
+                    };
+                    self.var_ref(slot, &index)?;
+                }
+                Op::SetIndex(slot, indexed, len) => {
+                    let value = self.pop();
+                    let keys = self.stack.split_off(self.stack.len() - len);
+                    let index = if indexed { self.pop_index() } else { None };
+                    Fun::set_index(self.var_mut(slot, &index)?, &keys, value)?;
+                }
+                Op::Jump(to) => {
+                    frame.ip = to;
+                }
+                Op::JumpUnless(to) => {
+                    let check = match self.stack.pop() {
+                        Some(Expr::Bool(t)) => t,
+                        Some(value) => Expr::parse_exp_boolean(value),
+                        None => false,
+                    };
+                    if !check {
+                        self.frame().ip = to;
+                    }
+                }
+                Op::EachStart => {
+                    // maps hand out their keys, strings their chars
+                    let items: Vec<Expr> = match self.pop() {
+                        Expr::List(items) => items,
+                        Expr::Map(entries) => entries.into_keys().map(Expr::String).collect(),
+                        Expr::String(t) => t.chars().map(|ch| Expr::String(ch.to_string())).collect(),
+                        _ => {
+                            return Err(LigmaError::new(ErrorKind::TypeMismatch, String::from("only lists, maps and strings can be looped over")));
+                        }
+                    };
+                    self.frame().items.push(items.into_iter());
+                }
+                Op::EachNext(slot, done) => {
+                    match frame.items.last_mut().and_then(|items| items.next()) {
+                        Some(item) => {
+                            self.burn(1)?;
+                            self.set_var(slot, None, item)?;
+                        }
+                        None => {
+                            frame.items.pop();
+                            frame.ip = done;
+                        }
+                    }
+                }
+                Op::EachEnd => {
+                    frame.items.pop();
+                }
+                Op::Output => {
+                    let to_print = match self.pop() {
+                        Expr::Bool(t) => t.to_string(),
+                        Expr::Int(t) => t.to_string(),
+                        Expr::Float(t) => t.to_string(),
+                        Expr::String(t) => t,
+                        exp @ (Expr::List(_) | Expr::Map(_)) => Expr::parse_exp_string(exp),
+                        _ => {
+                            return Err(LigmaError::unexpected("that holds nothing printable"));
+                        }
+                    };
+                    self.print(&to_print)?;
+                }
+                Op::OutputStr(i) => {
+                    let chunk = Rc::clone(&frame.chunk);
+                    for to_print in &chunk.texts[i] {
+                        self.print(to_print)?;
+                    }
+                }
+                Op::Input(slot) => {
+                    let input = match self.input.read_line() {
+                        Some(line) => Expr::String(line),
+                        None => {
+                            let msg = format!("ran out of input to read into `{}`", self.frame().name(slot));
+                            return Err(LigmaError::new(ErrorKind::NoInput, msg));
+                        }
+                    };
+                    self.push_checked(input)?;
+                }
+                Op::Function(i) => {
+                    let name = self.functions[i].name.clone();
+                    self.declared.insert(name, i);
+                }
+                Op::Def(i) => {
+                    let (name, body) = frame.chunk.defs[i].clone();
+                    self.defs.insert(name, body);
+                }
+                Op::Insert(i, to) => {
+                    let name = frame.chunk.inserts[i].clone();
+                    self.insert(&name, to)?;
+                }
+                Op::BreakOut(span) => {
+                    let span = frame.chunk.spans[span].clone();
+                    self.break_out(span)?;
+                }
+                Op::FindCall(site, builtin) => {
+                    let site = &frame.chunk.calls[site];
+                    match self.declared.get(&site.name) {
+                        Some(&function) => {
+                            // f() is a perfectly fine way to call something that doesn't care about _@
+                            let mut arity = self.functions[function].arity;
+                            if arity == 0 && site.args == 1 {
+                                arity = 1;
+                            }
+                            if arity != site.args {
+                                let msg = format!("`{}` takes {} argument(s) but was given {}", site.name, arity.max(1), site.args);
+                                return Err(LigmaError::new(ErrorKind::ArgumentCount, msg));
+                            }
+                            self.callees.push(function);
+                        }
+                        None => {
+                            frame.ip = builtin;
+                        }
+                    }
+                }
+                Op::Arg => {
+                    let arg = Var {
+                        plain: Some(self.pop()),
+                        indexed: HashMap::new(),
+                    };
+                    self.args.push(arg);
+                }
+                Op::ArgVar(slot, span) => {
+                    match &frame.vars[slot] {
+                        Some(var) => {
+                            let arg = var.clone();
+                            self.args.push(arg);
+                        }
+                        None => {
+                            let span = frame.chunk.spans[span].clone();
+                            return Err(LigmaError::undefined_variable(frame.name(slot)).with_span(span));
+                        }
+                    }
+                }
+                Op::Call(site, want) => {
+                    self.call(site, want)?;
+                }
+                Op::Len => {
+                    let value = self.pop();
+                    self.stack.push(Fun::length(&value)?);
+                }
+                Op::LenSlot(slot, span) => {
+                    let value = match self.var_ref(slot, &None) {
+                        Ok(value) => Fun::length(value)?,
+                        Err(e) => {
+                            return Err(e.with_span(self.frame().chunk.spans[span].clone()));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::ListCheck(slot, indexed, site) => {
+                    let index = match indexed {
+                        true => self.stack.last().cloned().map(Expr::parse_exp_string),
+                        false => None,
+                    };
+                    self.list_mut(slot, &index, site)?;
+                }
+                Op::ListPush(slot, indexed, site) => {
+                    let value = self.pop();
+                    let index = if indexed { self.pop_index() } else { None };
+                    let items = self.list_mut(slot, &index, site)?;
+                    items.push(value);
+                    let len = items.len() as i64;
+                    self.stack.push(Expr::Int(len));
+                }
+                Op::ListPop(slot, indexed, site) => {
+                    let index = if indexed { self.pop_index() } else { None };
+                    let value = match self.list_mut(slot, &index, site)?.pop() {
+                        Some(value) => value,
+                        None => {
+                            let msg = format!("can't pop from empty list `{}`", self.frame().name(slot));
+                            return Err(LigmaError::new(ErrorKind::BadIndex, msg));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::Return(has_value) => {
+                    let value = if has_value { Some(self.pop()) } else { None };
+                    if self.finish_function(value)? {
+                        return Ok(());
+                    }
+                }
+                Op::Fail(i) => {
+                    return Err(frame.chunk.errors[i].clone());
+                }
+            }
+        }
+    }
+
+    fn load(&mut self, slot: usize, index: Option<String>, span: Option<usize>) -> Result<Expr, LigmaError> {
+        match self.get_var(slot, &index) {
+            Ok(value) => {
+                return Ok(value);
+            }
+            Err(e) => match span {
+                Some(span) => {
+                    return Err(e.with_span(self.frame().chunk.spans[span].clone()));
+                }
+                None => {
+                    return Err(e);
+                }
+            },
+        }
+    }
+
+    // Both sides ints, worked out without copying either of them anywhere.
+    // None sends it the long way round, which is also where a missing
+    // variable gets its error.
+    fn int_binop(&mut self, chunk: &Chunk, op: &Token, left: Operand, right: Operand) -> Option<Result<Expr, LigmaError>> {
+        let len = self.stack.len();
+        let (exp_a, exp_b, taken) = match (left, right) {
+            (Operand::Stack, Operand::Stack) => (self.peek_int(chunk, Operand::Stack, 2)?, self.peek_int(chunk, Operand::Stack, 1)?, 2),
+            (Operand::Stack, right) => (self.peek_int(chunk, Operand::Stack, 1)?, self.peek_int(chunk, right, 0)?, 1),
+            (left, right) => (self.peek_int(chunk, left, 0)?, self.peek_int(chunk, right, 0)?, 0),
+        };
+        let value = Fun::int_binop(exp_a, op, exp_b)?;
+        self.stack.truncate(len - taken);
+        return Some(value);
+    }
+
+    // from is how far down the stack a Stack operand is
+    fn peek_int(&self, chunk: &Chunk, operand: Operand, from: usize) -> Option<i64> {
+        let value = match operand {
+            Operand::Stack => self.stack.get(self.stack.len().checked_sub(from)?)?,
+            Operand::Slot(slot, _) => self.frames.last()?.vars[slot as usize].as_ref()?.plain.as_ref()?,
+            Operand::Const(i) => &chunk.consts[i as usize],
+        };
+        match value {
+            Expr::Int(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    fn operand(&mut self, chunk: &Chunk, operand: Operand) -> Result<Expr, LigmaError> {
+        match operand {
+            Operand::Slot(slot, span) => {
+                return self.load(slot as usize, None, Some(span as usize));
+            }
+            Operand::Const(i) => {
+                let value = chunk.consts[i as usize].clone();
+                self.check_string(&value)?;
+                return Ok(value);
+            }
+            Operand::Stack => {
+                return Ok(self.pop());
+            }
+        }
+    }
+
+    fn argreg_slot(&mut self, index: Option<String>, span: usize) -> Result<Expr, LigmaError> {
+        if let Some(value) = self.argreg.get(&index) {
+            return Ok(value.clone());
+        }
+        let span = self.frame().chunk.spans[span].clone();
+        return Err(LigmaError::undefined_argreg(&index).with_span(span));
+    }
+
+    fn call(&mut self, site: usize, want: bool) -> Result<(), LigmaError> {
+        let function = self.callees.pop().unwrap_or_default();
+        let caller = self.frames.last().unwrap();
+        let args = caller.chunk.calls[site].args;
+        if self.depth + 1 >= self.config.max_call_depth {
+            let name = &caller.chunk.calls[site].name;
+            let msg = format!("calling `{}` would go deeper than {} frames", name, self.config.max_call_depth);
+            return Err(LigmaError::new(ErrorKind::LimitExceeded, msg));
+        }
+        self.depth += 1;
+        let chunk = Rc::clone(&self.functions[function]);
+        let mut frame = Frame::new(chunk, FrameKind::Call(site, want), self.stack.len());
+        let args = self.args.split_off(self.args.len() - args);
+        for (at, arg) in args.into_iter().enumerate() {
+            let slot = frame.chunk.params[at];
+            if frame.vars[slot].is_none() {
+                frame.live += 1;
+            }
+            frame.vars[slot] = Some(arg);
+        }
+        self.frames.push(frame);
+        return Ok(());
+    }
+
+    // Falling off the end of a frame. #(def) code hands the vars back and the
+    // frame it was inserted into carries on, anything else is a function done.
+    // true once the whole program is.
+    fn finish(&mut self, value: Option<Expr>) -> Result<bool, LigmaError> {
+        if let FrameKind::Insert(_) = self.frame().kind {
+            self.leave_insert();
+            return Ok(false);
+        }
+        return self.finish_function(value);
+    }
+
+    // @@ from wherever, #(def) code included, ends the function it's in
+    fn finish_function(&mut self, value: Option<Expr>) -> Result<bool, LigmaError> {
+        loop {
+            let frame = match self.frames.pop() {
+                Some(frame) => frame,
+                None => {
+                    return Ok(true);
+                }
+            };
+            match frame.kind {
+                FrameKind::Insert(_) => {}
+                FrameKind::Main => {
+                    // a @@ out at the top just ends the program early
+                    return Ok(true);
+                }
+                FrameKind::Call(site, want) => {
+                    self.depth -= 1;
+                    self.stack.truncate(frame.base);
+                    if !want {
+                        return Ok(false);
+                    }
+                    match value {
+                        Some(value) => {
+                            self.stack.push(value);
+                        }
+                        None => {
+                            // nothing came back through @@, so fall back on @
+                            let span = self.frame().chunk.calls[site].span.clone();
+                            let value = match self.argreg.get(&None) {
+                                Some(value) => value.clone(),
+                                None => {
+                                    return Err(LigmaError::undefined_argreg(&None).with_span(span));
+                                }
+                            };
+                            self.stack.push(value);
+                        }
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    // a def only gets compiled the first time it lands somewhere, after that
+    // inserting it is as cheap as a call
+    fn insert(&mut self, name: &str, to: Option<usize>) -> Result<(), LigmaError> {
+        let body = match self.defs.get(name) {
+            Some(def) => Arc::clone(def),
+            None => {
+                return Err(LigmaError::new(ErrorKind::UndefinedDef, format!("undefined def `{}`", name)));
+            }
+        };
+        let stmts = match &*body {
+            Ok(stmts) => stmts,
+            Err(e) => {
+                return Err(e.clone());
+            }
+        };
+        let frame = self.frames.last().unwrap();
+        let key = (Arc::as_ptr(&body) as usize, Rc::as_ptr(&frame.chunk) as usize, frame.extra.clone());
+        let chunk = match self.expanded.get(&key) {
+            Some(chunk) => Rc::clone(chunk),
+            None => {
+                let mut names = frame.chunk.names.clone();
+                names.extend(frame.extra.iter().cloned());
+                let program = Compiler::new(&self.config, self.functions.len()).compile_insert(name, stmts, names);
+                self.functions.extend(program.functions.into_iter().map(Rc::new));
+                let chunk = Rc::new(program.main);
+                self.expanded.insert(key, Rc::clone(&chunk));
+                chunk
+            }
+        };
+
+        let base = self.stack.len();
+        let parent = self.frame();
+        let mut frame = Frame::new(chunk, FrameKind::Insert(to), base);
+        let mut vars = std::mem::take(&mut parent.vars);
+        vars.resize(frame.chunk.names.len(), None);
+        frame.vars = vars;
+        frame.live = parent.live;
+        self.frames.push(frame);
+        return Ok(());
+    }
+
+    // gives the vars back to whoever inserted the def, with any new names
+    fn leave_insert(&mut self) -> Option<usize> {
+        let frame = self.frames.pop().unwrap();
+        let parent = self.frame();
+        parent.extra = frame.chunk.names[parent.chunk.names.len()..].to_vec();
+        parent.vars = frame.vars;
+        parent.live = frame.live;
+        match frame.kind {
+            FrameKind::Insert(to) => to,
+            _ => None,
+        }
+    }
+
+    // a $; in #(def) code breaks whatever loop the #(def) sits in, however
+    // many inserts deep that is
+    fn break_out(&mut self, span: crate::ligma::serendipity::Span) -> Result<(), LigmaError> {
+        loop {
+            match self.leave_insert() {
+                Some(to) => {
+                    self.frame().ip = to;
+                    return Ok(());
+                }
+                None => {
+                    if let FrameKind::Insert(_) = self.frame().kind {
+                        continue;
+                    }
+                    return Err(LigmaError::unexpected("`$;` has no loop to break out of").with_span(span));
+                }
+            }
+        }
+    }
+}
@@ -1,69 +1,529 @@
-mod ligma;
-use std::io;
-use ligma::{
-    fun::Fun as Fun,
-    lexer::Lexer as Lexer,
-    lexer::Token as Token,
-    expr::Expr as Expr,
+mod repl;
+use wasm_ligma_interpreter::{
+    parse_source_with,
+    run_source,
+    Engine,
+    RunOptions,
+    ligma::{
+        lexer::Lexer as Lexer,
+        include::IncludeOptions,
+        lexer::Spanned as Spanned,
+        stmt::Stmt as Stmt,
+        input::{InputSource, QueueInput, StdinInput},
+        output::StdoutSink,
+        config::InterpreterConfig,
+        fmt::format_source,
+        compile::Compiler,
+    },
 };
-use std::panic;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
 use std::env;
 
-fn main() {
-    let mut tokens: Vec<Token> = Vec::new();
-    let args: Vec<String> = env::args().collect();
-    let mut debug: bool = false;
-    if let Some(opt) = args.get(2) {
-        debug = opt == "debug";
-    }
-    if let Some(input_file) = args.get(1) {
-        match Lexer::read_file(input_file.to_string()) {
-            Ok(file_contents) => {
-                let mut lexer = Lexer::new(&file_contents);
-                loop {
-                    let token = lexer.lex_next_token();
-                    match token {
-                        Token::EOF => {
-                            break;
-                        }
-                        Token::Include(to_include) => {
-                            for token_to_include in to_include {
-                                if debug {
-                                    println!("{:?}", token_to_include);
-                                }
-                                tokens.push(token_to_include);
-                            }
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+const USAGE: &str = "\
+usage: ligma <command> [options]
+
+commands:
+  run <file>       run a program (ligma <file> does the same)
+  check <file>     lex and parse without running, exits 1 on errors
+  tokens <file>    dump every token with where it came from
+  ast <file>       dump the parsed program
+  bytecode <file>  dump what the program compiles to
+  fmt <file>       print the program tidied up
+  includes <file>  show which file includes which
+  repl             type it in yourself (so does ligma on its own)
+
+everywhere:
+  -I, --include-path <dir>     look in here for #[path] too, can be given more than once.
+                               $LIGMA_PATH is searched after these
+
+run, repl and bytecode:
+  --fuel <steps>               stop after this many steps
+  --max-call-depth <n>         default 300
+  --max-expr-nesting <n>       default 300
+  --max-string-length <bytes>  default 16777216
+  --max-output-bytes <bytes>
+  --max-variables <n>          per function frame
+run:
+  --stdin <file>               ??name; reads lines from here instead of the terminal
+  --engine <bytecode|walk>     walk runs the tree directly like before, bytecode is the default
+tokens:
+  --json                       one JSON object per token
+includes:
+  --dot                        as a graphviz digraph instead of a tree
+fmt:
+  --write                      rewrite the file instead of printing it
+  --check                      exit 1 if the file isn't formatted already
+
+exit codes: 0 fine, 1 the program or its syntax is broken, 2 bad arguments or missing file";
+
+// Everything any subcommand can be handed. Which flags a command takes is
+// checked by parse_options, so run --json is an error and not a shrug.
+struct Options {
+    file: Option<String>,
+    fuel: Option<u64>,
+    config: InterpreterConfig,
+    includes: IncludeOptions,
+    stdin: Option<String>,
+    engine: Engine,
+    json: bool,
+    dot: bool,
+    write: bool,
+    check: bool,
+}
+
+fn usage_error(msg: &str) -> i32 {
+    eprintln!("ligma: {}\ntry `ligma help` for the list of commands and flags", msg);
+    return 2;
+}
+
+fn number<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    match value.and_then(|value| value.parse::<T>().ok()) {
+        Some(n) => Ok(n),
+        None => Err(format!("{} wants a number", flag)),
+    }
+}
+
+fn parse_options(args: &[String], allowed: &[&str]) -> Result<Options, String> {
+    let mut options = Options {
+        file: None,
+        fuel: None,
+        config: InterpreterConfig::default(),
+        includes: IncludeOptions::default(),
+        stdin: None,
+        engine: Engine::Bytecode,
+        json: false,
+        dot: false,
+        write: false,
+        check: false,
+    };
+    let mut at = 0;
+    while at < args.len() {
+        let arg = args[at].as_str();
+        at += 1;
+        if arg == "-I" || arg == "--include-path" {
+            match args.get(at) {
+                Some(dir) => options.includes.search_paths.push(PathBuf::from(dir)),
+                None => return Err(format!("{} wants a directory", arg)),
+            }
+            at += 1;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            if options.file.is_some() {
+                return Err(format!("one file at a time, got `{}` as well", arg));
+            }
+            options.file = Some(arg.to_string());
+            continue;
+        }
+        if !allowed.contains(&arg) {
+            return Err(format!("unknown flag `{}`", arg));
+        }
+        let value = args.get(at);
+        match arg {
+            "--json" => {
+                options.json = true;
+            }
+            "--dot" => {
+                options.dot = true;
+            }
+            "--write" => {
+                options.write = true;
+            }
+            "--check" => {
+                options.check = true;
+            }
+            _ => {
+                // the rest all take a value
+                at += 1;
+                match arg {
+                    "--fuel" => options.fuel = Some(number(arg, value)?),
+                    "--max-call-depth" => options.config.max_call_depth = number(arg, value)?,
+                    "--max-expr-nesting" => options.config.max_expr_nesting = number(arg, value)?,
+                    "--max-string-length" => options.config.max_string_length = Some(number(arg, value)?),
+                    "--max-output-bytes" => options.config.max_output_bytes = Some(number(arg, value)?),
+                    "--max-variables" => options.config.max_variables = Some(number(arg, value)?),
+                    "--stdin" => {
+                        match value {
+                            Some(path) => options.stdin = Some(path.clone()),
+                            None => return Err(String::from("--stdin wants a file")),
                         }
-                        _ => {
-                            if debug {
-                                println!("{:?}", token);
-                            }
-                            tokens.push(token);
+                    }
+                    "--engine" => {
+                        match value.map(|value| value.as_str()) {
+                            Some("bytecode") => options.engine = Engine::Bytecode,
+                            Some("walk") => options.engine = Engine::Walk,
+                            _ => return Err(String::from("--engine wants bytecode or walk")),
                         }
                     }
-                    
+                    _ => return Err(format!("unknown flag `{}`", arg)),
                 }
             }
-            Err(_) => {
-                println!("file not found");
+        }
+    }
+    // the environment comes after anything on the command line
+    if let Some(paths) = env::var_os("LIGMA_PATH") {
+        options.includes.search_paths.extend(env::split_paths(&paths));
+    }
+    options.includes.path = options.file.as_ref().map(PathBuf::from);
+    return Ok(options);
+}
+
+const LIMIT_FLAGS: [&str; 6] = ["--fuel", "--max-call-depth", "--max-expr-nesting", "--max-string-length", "--max-output-bytes", "--max-variables"];
 
+// the file a command was pointed at, or an exit code if there isn't one
+fn read_source(options: &Options) -> Result<(String, String), i32> {
+    let path = match &options.file {
+        Some(path) => path.clone(),
+        None => return Err(usage_error("which file?")),
+    };
+    match Lexer::read_file(path.clone()) {
+        Ok(source) => Ok((path, source)),
+        Err(e) => {
+            eprintln!("ligma: can't read {}: {}", path, e);
+            return Err(2);
+        }
+    }
+}
+
+// lex and parse, and report everything that went wrong on the way
+fn load(source: &str, options: &Options) -> Result<Vec<Stmt>, i32> {
+    match parse_source_with(source, &options.includes, &options.config) {
+        Ok(program) => Ok(program),
+        Err(errors) => {
+            for e in errors {
+                eprint!("{}", e.render(source));
             }
+            return Err(1);
+        }
+    }
+}
+
+fn run(args: &[String]) -> i32 {
+    let mut allowed = LIMIT_FLAGS.to_vec();
+    allowed.push("--stdin");
+    allowed.push("--engine");
+    let options = match parse_options(args, &allowed) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (_, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    let input = match &options.stdin {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => Some(text.lines().map(|line| line.to_string()).collect::<Vec<String>>()),
+            Err(e) => {
+                eprintln!("ligma: can't read {}: {}", path, e);
+                return 2;
+            }
+        },
+        None => None,
+    };
+    let input: Box<dyn InputSource> = match input {
+        Some(lines) => Box::new(QueueInput::new(lines)),
+        None => Box::new(StdinInput),
+    };
+    let result = run_source(&source, RunOptions {
+        config: options.config,
+        fuel: options.fuel,
+        engine: options.engine,
+        includes: options.includes,
+        input,
+        output: Box::new(StdoutSink),
+    });
+    for e in &result.errors {
+        eprint!("{}", e.render(&source));
+    }
+    return if result.ok() { 0 } else { 1 };
+}
+
+fn check(args: &[String]) -> i32 {
+    let options = match parse_options(args, &[]) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (path, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    if let Err(code) = load(&source, &options) {
+        return code;
+    }
+    println!("{}: ok", path);
+    return 0;
+}
+
+// the name of the variant, Identifier(None, "a") is an Identifier
+fn token_kind(spanned: &Spanned) -> String {
+    let debug = format!("{:?}", spanned.token);
+    return debug.split('(').next().unwrap_or("").to_string();
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
         }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+fn tokens(args: &[String]) -> i32 {
+    let options = match parse_options(args, &["--json"]) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (_, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    let mut lexer = Lexer::new(&source);
+    lexer.set_includes(&options.includes);
+    for spanned in lexer.lex_all() {
+        if options.json {
+            let file = match &spanned.span.file {
+                Some(file) => json_string(file),
+                None => String::from("null"),
+            };
+            println!(
+                "{{\"kind\": {}, \"token\": {}, \"line\": {}, \"column\": {}, \"start\": {}, \"end\": {}, \"file\": {}}}",
+                json_string(&token_kind(&spanned)),
+                json_string(&format!("{:?}", spanned.token)),
+                spanned.span.line,
+                spanned.span.column,
+                spanned.span.start,
+                spanned.span.end,
+                file,
+            );
+        } else {
+            println!("{} {:?}", spanned.span, spanned.token);
+        }
+    }
+    // the dump is still worth having when some of it is Illegal
+    if lexer.diagnostics().len() > 0 {
+        for diagnostic in lexer.diagnostics() {
+            eprintln!("{}", diagnostic.render(&source));
+        }
+        return 1;
+    }
+    return 0;
+}
+
+fn ast(args: &[String]) -> i32 {
+    let options = match parse_options(args, &[]) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (_, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    match load(&source, &options) {
+        Ok(program) => {
+            println!("{:#?}", program);
+            return 0;
+        }
+        Err(code) => return code,
+    }
+}
+
+// the limits decide where Fail ops end up, so they're taken here too
+fn bytecode(args: &[String]) -> i32 {
+    let options = match parse_options(args, &LIMIT_FLAGS) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (_, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    match load(&source, &options) {
+        Ok(program) => {
+            print!("{}", Compiler::new(&options.config, 0).compile(&program).disassemble());
+            return 0;
+        }
+        Err(code) => return code,
+    }
+}
+
+fn includes(args: &[String]) -> i32 {
+    let options = match parse_options(args, &["--dot"]) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    let (_, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    let mut lexer = Lexer::new(&source);
+    lexer.set_includes(&options.includes);
+    lexer.lex_all();
+    let graph = lexer.include_graph();
+    if options.dot {
+        print!("{}", graph.to_dot());
     } else {
-        println!("expected a file");
+        print!("{}", graph.to_text());
     }
+    // a missing file or a cycle still gets drawn, it's just not a success
+    if lexer.diagnostics().len() > 0 {
+        for diagnostic in lexer.diagnostics() {
+            eprint!("{}", diagnostic.render(&source));
+        }
+        return 1;
+    }
+    return 0;
+}
 
-    if !debug{
-        let result = panic::catch_unwind(|| {
-            let mut fun = Fun::new(tokens);
-            fun.eval(false);
-        });
-        if let Err(_) = result {
-            println!("Well that happened.. this shouldn\'t be possible. Send me what your program is");
-            // Handle the stack overflow here
+fn fmt(args: &[String]) -> i32 {
+    let options = match parse_options(args, &["--write", "--check"]) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    if options.write && options.check {
+        return usage_error("--write and --check don't go together");
+    }
+    let (path, source) = match read_source(&options) {
+        Ok(read) => read,
+        Err(code) => return code,
+    };
+    let formatted = match format_source(&source, &options.includes) {
+        Ok(formatted) => formatted,
+        Err(errors) => {
+            for e in errors {
+                eprint!("{}", e.render(&source));
+            }
+            return 1;
+        }
+    };
+    if options.check {
+        if formatted != source {
+            eprintln!("{} isn't formatted", path);
+            return 1;
         }
+        return 0;
     }
+    if options.write {
+        if formatted != source {
+            if let Err(e) = fs::write(&path, formatted) {
+                eprintln!("ligma: can't write {}: {}", path, e);
+                return 2;
+            }
+        }
+        return 0;
+    }
+    print!("{}", formatted);
+    return 0;
+}
 
+fn repl(args: &[String]) -> i32 {
+    let options = match parse_options(args, &LIMIT_FLAGS) {
+        Ok(options) => options,
+        Err(msg) => return usage_error(&msg),
+    };
+    if let Some(file) = options.file {
+        return usage_error(&format!("the repl doesn't take a file, try `ligma run {}`", file));
+    }
+    repl::run(options.fuel, options.config, options.includes);
+    return 0;
+}
 
+fn command(args: Vec<String>) -> i32 {
+    match args.first().map(|arg| arg.as_str()) {
+        None => repl(&[]),
+        Some("run") => run(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("tokens") => tokens(&args[1..]),
+        Some("ast") => ast(&args[1..]),
+        Some("bytecode") => bytecode(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+        Some("includes") => includes(&args[1..]),
+        Some("repl") => repl(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            0
+        }
+        // ligma <file> [debug] and ligma --fuel 10 from before there were commands
+        Some(_) => {
+            let file = args.iter().find(|arg| !arg.starts_with("--") && arg.parse::<u64>().is_err());
+            if file.is_none() {
+                repl(&args)
+            } else if args.iter().any(|arg| arg == "debug") {
+                let rest: Vec<String> = args.iter().filter(|arg| *arg != "debug").cloned().collect();
+                tokens(&rest)
+            } else {
+                run(&args)
+            }
+        }
+    }
 }
 
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // the default call depth, and parsing 300 nested blocks, need more room
+    // than the main thread gets in a debug build, so everything runs on a
+    // thread that has plenty
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        return command(args);
+    });
+    match runner.map(|runner| runner.join()) {
+        Ok(Ok(code)) => std::process::exit(code),
+        _ => {
+            eprintln!("Well that happened.. this shouldn\'t be possible. Send me what your program is");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a file on disk for the command to be pointed at, named after the test so they can run at once
+    fn file(name: &str, source: &str) -> String {
+        let path = env::temp_dir().join(format!("ligma-{}-{}.ligma", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        return path.display().to_string();
+    }
+
+    fn exit_code(args: &[&str]) -> i32 {
+        return command(args.iter().map(|arg| arg.to_string()).collect());
+    }
+
+    #[test]
+    fn exit_codes_say_whose_fault_it_was() {
+        let good = file("good", "a = 1 + 2;");
+        let broken = file("broken", "a = 1 / 0;");
+        let bad_syntax = file("bad-syntax", "a = 1 ` 2;");
+        assert_eq!(exit_code(&["run", &good]), 0);
+        assert_eq!(exit_code(&["check", &good]), 0);
+        assert_eq!(exit_code(&[&good]), 0);
+        // the program is broken
+        assert_eq!(exit_code(&["run", &broken]), 1);
+        assert_eq!(exit_code(&["run", &bad_syntax]), 1);
+        assert_eq!(exit_code(&["check", &bad_syntax]), 1);
+        assert_eq!(exit_code(&["run", &good, "--fuel", "1"]), 1);
+        // the command is
+        assert_eq!(exit_code(&["run", "/no/such/file.ligma"]), 2);
+        assert_eq!(exit_code(&["run"]), 2);
+        assert_eq!(exit_code(&["run", &good, "--json"]), 2);
+        assert_eq!(exit_code(&["run", &good, "--fuel", "lots"]), 2);
+        assert_eq!(exit_code(&["check", &good, &broken]), 2);
+        for path in [good, broken, bad_syntax] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use wasm_ligma_interpreter::ligma::{
+    fun::Fun as Fun,
+    lexer::Lexer as Lexer,
+    include::IncludeOptions,
+    lexer::Spanned as Spanned,
+    lexer::Token as Token,
+    parser::Parser as Parser,
+    expr::Expr as Expr,
+    shared::Shared,
+    output::{OutputSink, StdoutSink},
+    config::InterpreterConfig,
+    serendipity::ErrorKind,
+};
+
+const HELP: &str = "\
+:help          this
+:vars          every variable and what it holds
+:funcs         every function that has been declared
+:defs          every def that has been defined
+:reset         forget all of the above
+:quit          leave (so does ctrl-d)
+anything else is ligma, a line with an open { keeps reading until it's closed";
+
+// stdout that remembers whether the last thing printed finished its line,
+// so the next prompt doesn't end up glued onto the output
+struct ReplSink {
+    dangling: Shared<bool>,
+}
+
+impl OutputSink for ReplSink {
+    fn write(&mut self, chunk: &str) {
+        StdoutSink.write(chunk);
+        if let Some(ch) = chunk.chars().last() {
+            *self.dangling.get() = ch != '\n';
+        }
+    }
+}
+
+struct Repl {
+    fun: Fun,
+    fuel: Option<u64>,
+    config: InterpreterConfig,
+    dangling: Shared<bool>,
+}
+
+impl Repl {
+    fn new(fuel: Option<u64>, config: InterpreterConfig) -> Repl {
+        let mut repl = Repl {
+            fun: Fun::new(Vec::new(), config.clone()),
+            fuel,
+            config,
+            dangling: Shared::new(false),
+        };
+        repl.reset();
+        return repl;
+    }
+
+    fn reset(&mut self) {
+        self.fun = Fun::new(Vec::new(), self.config.clone());
+        self.fun.set_output(Box::new(ReplSink { dangling: self.dangling.clone() }));
+    }
+
+    // false once it's time to leave
+    fn command(&mut self, command: &str) -> bool {
+        match command {
+            ":help" => {
+                println!("{}", HELP);
+            }
+            ":vars" => {
+                for (name, value) in self.fun.var_values() {
+                    println!("{} = {}", name, show(value));
+                }
+            }
+            ":funcs" => {
+                for (name, params) in self.fun.function_names() {
+                    if params.len() == 0 {
+                        println!("{}{{ }}", name);
+                    } else {
+                        println!("{}({}){{ }}", name, params.join(", "));
+                    }
+                }
+            }
+            ":defs" => {
+                for name in self.fun.def_names() {
+                    println!("#<{}>", name);
+                }
+            }
+            ":reset" => {
+                self.reset();
+            }
+            ":quit" | ":q" => {
+                return false;
+            }
+            _ => {
+                println!("no idea what {} is, try :help", command);
+            }
+        }
+        return true;
+    }
+
+    // A line without a ; on the end that makes sense as one expression gets
+    // its value printed, sq(a) included. Everything else runs like it would in a file.
+    fn eval(&mut self, chunk: &str, tokens: Vec<Spanned>) {
+        self.fun.set_fuel(self.fuel);
+        let expression = match tokens.last() {
+            Some(Spanned { token: Token::Semicolon, .. }) | Some(Spanned { token: Token::RBrack, .. }) => None,
+            _ => {
+                let mut parser = Parser::new(tokens.clone());
+                parser.set_max_nesting(self.config.max_expr_nesting);
+                parser.parse_expression().ok()
+            }
+        };
+        let result = match expression {
+            Some(exp) => {
+                self.fun.eval_expression(&exp).map(|value| {
+                    println!("{}", show(value));
+                })
+            }
+            None => {
+                let mut parser = Parser::new(tokens);
+                parser.set_max_nesting(self.config.max_expr_nesting);
+                match parser.parse() {
+                    Ok(program) => self.fun.eval_chunk(program),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if std::mem::replace(&mut *self.dangling.get(), false) {
+            println!();
+        }
+        if let Err(e) = result {
+            eprint!("{}", e.render(chunk));
+        }
+    }
+}
+
+// strings get their quotes so "1" and 1 don't look the same
+fn show(value: Expr) -> String {
+    match value {
+        Expr::String(t) => format!("{:?}", t),
+        _ => Expr::parse_exp_string(value),
+    }
+}
+
+// how many blocks are still waiting for their }
+fn open_blocks(tokens: &[Spanned]) -> i64 {
+    let mut depth = 0;
+    for spanned in tokens {
+        match spanned.token {
+            Token::Function(_) | Token::If(_,_) | Token::ElseIf(_,_) | Token::Else | Token::Loop(_,_) | Token::LBrack => {
+                depth += 1;
+            }
+            Token::RBrack => {
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    return depth;
+}
+
+pub fn run(fuel: Option<u64>, config: InterpreterConfig, includes: IncludeOptions) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("couldn't start the repl: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("L I G M A  Interprets Generally Meaningless Abstractions\nv0.1.0\n:help for help, :quit to leave");
+    let mut repl = Repl::new(fuel, config);
+    let mut chunk = String::new();
+    loop {
+        let prompt = if chunk.len() == 0 { "ligma> " } else { "  ...> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // ctrl-c throws away whatever was half typed
+                chunk.clear();
+                continue;
+            }
+            Err(_) => {
+                break;
+            }
+        };
+        if chunk.len() == 0 && line.trim().starts_with(':') {
+            let _ = editor.add_history_entry(line.as_str());
+            if !repl.command(line.trim()) {
+                break;
+            }
+            continue;
+        }
+        chunk.push_str(&line);
+        chunk.push('\n');
+        let mut lexer = Lexer::new(&chunk);
+        lexer.set_includes(&includes);
+        let tokens = lexer.lex_all();
+        // an unfinished string or comment, or a block that's still open, wants more lines
+        let unfinished = lexer.diagnostics().iter()
+            .any(|diagnostic| diagnostic.kind == ErrorKind::UnexpectedEof);
+        if unfinished || open_blocks(&tokens) > 0 {
+            continue;
+        }
+        let _ = editor.add_history_entry(chunk.trim_end());
+        if lexer.diagnostics().len() > 0 {
+            for diagnostic in lexer.diagnostics() {
+                eprintln!("{}", diagnostic.render(&chunk));
+            }
+        } else if tokens.len() > 0 {
+            repl.eval(&chunk, tokens);
+        }
+        chunk.clear();
+    }
+}